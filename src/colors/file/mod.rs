@@ -0,0 +1,399 @@
+use sfml::graphics::Color;
+use std::fmt;
+use std::path::Path;
+
+use super::mix;
+use super::palette::{Kind, Palette, Stop};
+
+/// gradient file formats of other fractal programs
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Ultra Fractal, the first gradient of the file
+    Ugr,
+    /// Fractint, one `r g b` line per color
+    Map,
+    /// GIMP
+    Ggr,
+    /// `position,r,g,b` lines, position in [0, 1] and channels in [0, 255]
+    Csv,
+}
+
+impl Format {
+    /// from the extension
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ugr" => Some(Format::Ugr),
+            "map" => Some(Format::Map),
+            "ggr" => Some(Format::Ggr),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Ugr => "ugr",
+            Format::Map => "map",
+            Format::Ggr => "ggr",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// the extension isn't one of `Format`
+    UnknownFormat,
+    /// `line` counts from 1
+    Parse {line: usize, message: String},
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::UnknownFormat => write!(f, "unknown gradient format, expected .ugr, .map, .ggr or .csv"),
+            Error::Parse {line, message} => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+fn error(line: usize, message: impl Into<String>) -> Error {
+    Error::Parse {line, message: message.into()}
+}
+
+/// the palette in the file at `path`, named after the file unless the file has a name
+pub fn load(path: &Path) -> Result<Palette, Error> {
+    let format = Format::of(path).ok_or(Error::UnknownFormat)?;
+    let text = std::fs::read_to_string(path)?;
+    let name = path.file_stem().map_or("palette".into(), |s| s.to_string_lossy());
+    parse(format, &name, &text)
+}
+
+/// writes `palette` to `path` in the format of its extension
+pub fn save(path: &Path, palette: &Palette) -> Result<(), Error> {
+    let format = Format::of(path).ok_or(Error::UnknownFormat)?;
+    std::fs::write(path, write(format, palette))?;
+    Ok(())
+}
+
+pub fn parse(format: Format, name: &str, text: &str) -> Result<Palette, Error> {
+    match format {
+        Format::Ugr => parse_ugr(name, text),
+        Format::Map => parse_map(name, text),
+        Format::Ggr => parse_ggr(name, text),
+        Format::Csv => parse_csv(name, text),
+    }
+}
+
+pub fn write(format: Format, palette: &Palette) -> String {
+    match format {
+        Format::Ugr => write_ugr(palette),
+        Format::Map => write_map(palette),
+        Format::Ggr => write_ggr(palette),
+        Format::Csv => write_csv(palette),
+    }
+}
+
+/// numbered lines, from 1
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines().enumerate().map(|(i, line)| (i + 1, line.trim()))
+}
+
+fn number<T: std::str::FromStr>(line: usize, s: &str, what: &str) -> Result<T, Error> {
+    s.parse().map_err(|_| error(line, format!("invalid {what} `{s}`")))
+}
+
+/// a channel in [0, 1] as a byte
+fn byte(x: f64) -> u8 {
+    (x.clamp(0., 1.)*255.).round() as u8
+}
+
+// Fractint: "r g b" per line, anything after the third number is a comment
+fn parse_map(name: &str, text: &str) -> Result<Palette, Error> {
+    let mut colors = Vec::new();
+    for (n, line) in lines(text) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let mut channel = || -> Result<u8, Error> {
+            let field = fields.next().ok_or_else(|| error(n, "expected 3 channels"))?;
+            number(n, field, "channel")
+        };
+        colors.push(Color::rgb(channel()?, channel()?, channel()?));
+    }
+    if colors.is_empty() {
+        return Err(error(1, "no colors"));
+    }
+    Ok(Palette::lut(name, colors))
+}
+
+fn write_map(palette: &Palette) -> String {
+    palette.sample(256).iter().map(|c| format!("{} {} {}\n", c.r, c.g, c.b)).collect()
+}
+
+// GIMP: a header, the optional name, the number of segments, then one segment per line:
+// left middle right, left rgba, right rgba, blending and coloring type
+fn parse_ggr(name: &str, text: &str) -> Result<Palette, Error> {
+    let mut lines = lines(text).filter(|(_, line)| !line.is_empty());
+    match lines.next() {
+        Some((_, "GIMP Gradient")) => (),
+        Some((n, _)) => return Err(error(n, "expected `GIMP Gradient`")),
+        None => return Err(error(1, "empty file")),
+    }
+    let mut name = name.to_string();
+    let (mut n, mut line) = lines.next().ok_or_else(|| error(2, "missing segment count"))?;
+    if let Some(title) = line.strip_prefix("Name:") {
+        name = title.trim().to_string();
+        (n, line) = lines.next().ok_or_else(|| error(n + 1, "missing segment count"))?;
+    }
+    let count: usize = number(n, line, "segment count")?;
+    let mut stops = Vec::new();
+    for i in 0..count {
+        let (n, line) = lines.next().ok_or_else(|| error(n + i + 1, format!("expected {count} segments, found {i}")))?;
+        let fields = line.split_whitespace().map(|s| number(n, s, "number")).collect::<Result<Vec<f64>, _>>()?;
+        if fields.len() < 11 {
+            return Err(error(n, format!("expected at least 11 numbers, found {}", fields.len())));
+        }
+        if fields.iter().any(|x| !x.is_finite()) {
+            return Err(error(n, "numbers have to be finite"));
+        }
+        let (left, middle, right) = (fields[0], fields[1], fields[2]);
+        if !(0. ..=1.).contains(&left) || !(left..=right).contains(&middle) || right > 1. {
+            return Err(error(n, "segment positions out of order"));
+        }
+        let a = Color::rgb(byte(fields[3]), byte(fields[4]), byte(fields[5]));
+        let b = Color::rgb(byte(fields[7]), byte(fields[8]), byte(fields[9]));
+        // every blending is taken as linear, in rgb
+        stops.push(Stop {position: left, color: a});
+        stops.push(Stop {position: middle, color: mix(a, b, 0.5)});
+        stops.push(Stop {position: right, color: b});
+    }
+    if stops.is_empty() {
+        return Err(error(n, "no segments"));
+    }
+    Ok(Palette::gradient(&name, stops))
+}
+
+fn write_ggr(palette: &Palette) -> String {
+    let stops = stops(palette);
+    let mut text = format!("GIMP Gradient\nName: {}\n{}\n", palette.name, stops.len() - 1);
+    for pair in stops.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let channel = |x: u8| x as f64 / 255.;
+        text += &format!(
+            "{:.6} {:.6} {:.6} {:.6} {:.6} {:.6} 1.000000 {:.6} {:.6} {:.6} 1.000000 0 0\n",
+            a.position, 0.5*(a.position + b.position), b.position,
+            channel(a.color.r), channel(a.color.g), channel(a.color.b),
+            channel(b.color.r), channel(b.color.g), channel(b.color.b),
+        );
+    }
+    text
+}
+
+// Ultra Fractal: `name { gradient: title=".." index=.. color=.. .. opacity: .. }`, colors are
+// 0xBBGGRR in decimal and indices go to 400
+const UGR_INDICES: f64 = 400.;
+
+fn parse_ugr(name: &str, text: &str) -> Result<Palette, Error> {
+    let mut name = name.to_string();
+    let mut inside = false;
+    let mut stops = Vec::new();
+    let mut last = 1;
+    for (n, line) in lines(text) {
+        last = n;
+        if !inside {
+            inside = line.starts_with("gradient:");
+            continue;
+        }
+        if line.starts_with("opacity:") || line.starts_with('}') {
+            break;
+        }
+        let mut index = None;
+        let mut color = None;
+        for field in line.split_whitespace() {
+            match field.split_once('=') {
+                Some(("title", title)) => name = title.trim_matches('"').to_string(),
+                Some(("index", value)) => index = Some(number::<i64>(n, value, "index")?),
+                Some(("color", value)) => color = Some(number::<u32>(n, value, "color")?),
+                _ => (),
+            }
+        }
+        match (index, color) {
+            (Some(index), Some(color)) => stops.push(Stop {
+                position: index as f64 / UGR_INDICES,
+                color: Color::rgb(color as u8, (color >> 8) as u8, (color >> 16) as u8),
+            }),
+            (None, None) => (),
+            _ => return Err(error(n, "expected both an index and a color")),
+        }
+    }
+    if !inside {
+        return Err(error(last, "no `gradient:` section"));
+    }
+    if stops.is_empty() {
+        return Err(error(last, "no colors"));
+    }
+    Ok(Palette::gradient(&name, stops))
+}
+
+fn write_ugr(palette: &Palette) -> String {
+    let title: String = palette.name.chars().filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_').collect();
+    let mut text = format!("{title} {{\ngradient:\n  title=\"{}\" smooth=yes\n", palette.name);
+    // the end wraps around to the start by itself
+    for stop in stops(palette).iter().filter(|s| s.position < 1.) {
+        let color = stop.color.r as u32 | (stop.color.g as u32) << 8 | (stop.color.b as u32) << 16;
+        text += &format!("  index={} color={color}\n", (stop.position*UGR_INDICES).round() as i64);
+    }
+    text += "opacity:\n  smooth=no index=0 opacity=255\n}\n";
+    text
+}
+
+// csv: `position,r,g,b`, a header line is allowed, `#` starts a comment line
+fn parse_csv(name: &str, text: &str) -> Result<Palette, Error> {
+    let mut stops = Vec::new();
+    let mut first = true;
+    for (n, line) in lines(text) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let header = first && fields[0].parse::<f64>().is_err();
+        first = false;
+        if header {
+            continue;
+        }
+        if fields.len() != 4 {
+            return Err(error(n, format!("expected 4 fields, found {}", fields.len())));
+        }
+        let position: f64 = number(n, fields[0], "position")?;
+        if !(0. ..=1.).contains(&position) {
+            return Err(error(n, format!("position {position} out of [0, 1]")));
+        }
+        let r = number(n, fields[1], "channel")?;
+        let g = number(n, fields[2], "channel")?;
+        let b = number(n, fields[3], "channel")?;
+        stops.push(Stop {position, color: Color::rgb(r, g, b)});
+    }
+    if stops.is_empty() {
+        return Err(error(1, "no colors"));
+    }
+    Ok(Palette::gradient(name, stops))
+}
+
+fn write_csv(palette: &Palette) -> String {
+    let mut text = String::from("position,r,g,b\n");
+    for stop in stops(palette) {
+        text += &format!("{:.6},{},{},{}\n", stop.position, stop.color.r, stop.color.g, stop.color.b);
+    }
+    text
+}
+
+/// stops from 0 to 1 that give the palette back, sampled when it has none
+fn stops(palette: &Palette) -> Vec<Stop> {
+    const SAMPLES: usize = 64;
+    let mut stops = match &palette.kind {
+        Kind::Gradient(stops) => stops.clone(),
+        _ => palette.sample(SAMPLES).into_iter().enumerate()
+            .map(|(i, color)| Stop {position: i as f64 / SAMPLES as f64, color})
+            .collect(),
+    };
+    // the palette wraps around, the color at both ends is the one at 0
+    let end = palette.color(0.);
+    if stops.first().is_none_or(|s| s.position > 0.) {
+        stops.insert(0, Stop {position: 0., color: end});
+    }
+    if stops.last().is_some_and(|s| s.position < 1.) {
+        stops.push(Stop {position: 1., color: end});
+    }
+    stops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(palette: &Palette) -> Vec<(f64, Color)> {
+        match &palette.kind {
+            Kind::Gradient(stops) => stops.iter().map(|s| (s.position, s.color)).collect(),
+            _ => panic!("{} isn't a gradient", palette.name),
+        }
+    }
+
+    fn line(result: Result<Palette, Error>) -> usize {
+        match result {
+            Err(Error::Parse {line, ..}) => line,
+            Err(e) => panic!("expected a parse error, got {e}"),
+            Ok(palette) => panic!("{} parsed", palette.name),
+        }
+    }
+
+    #[test]
+    fn parses_map() {
+        let palette = parse(Format::Map, "fire", "; comment\n255 0 0 red\n\n0 255 0\n0 0 255\n").unwrap();
+        assert!(palette.name == "fire");
+        match palette.kind {
+            Kind::Lut(colors) => assert!(colors == [Color::RED, Color::GREEN, Color::BLUE]),
+            _ => panic!("expected a lookup table"),
+        }
+    }
+
+    #[test]
+    fn parses_ggr() {
+        let text = "GIMP Gradient\nName: Sunset\n1\n0.000000 0.250000 1.000000 1 0 0 1 0 0 1 1 0 0\n";
+        let palette = parse(Format::Ggr, "file", text).unwrap();
+        assert!(palette.name == "Sunset");
+        assert!(positions(&palette) == [(0., Color::RED), (0.25, mix(Color::RED, Color::BLUE, 0.5)), (1., Color::BLUE)]);
+    }
+
+    #[test]
+    fn parses_ugr() {
+        let text = "fire {\ngradient:\n  title=\"Fire\" smooth=yes\n  index=0 color=255\n  index=200 color=16711680\nopacity:\n  smooth=no index=0 opacity=255\n}\n";
+        let palette = parse(Format::Ugr, "file", text).unwrap();
+        assert!(palette.name == "Fire");
+        assert!(positions(&palette) == [(0., Color::RED), (0.5, Color::BLUE)]);
+    }
+
+    #[test]
+    fn parses_csv() {
+        let palette = parse(Format::Csv, "ice", "position,r,g,b\n# comment\n1,0,0,255\n0, 255, 0, 0\n").unwrap();
+        assert!(palette.name == "ice");
+        assert!(positions(&palette) == [(0., Color::RED), (1., Color::BLUE)]);
+    }
+
+    /// every format gives back the colors it was given, to a unit of a channel
+    #[test]
+    fn round_trips() {
+        let palette = Palette::gradient("round trip", vec![
+            Stop {position: 0., color: Color::rgb(10, 20, 30)},
+            Stop {position: 0.25, color: Color::rgb(200, 100, 0)},
+            Stop {position: 0.625, color: Color::rgb(0, 255, 128)},
+        ]);
+        for format in [Format::Ugr, Format::Map, Format::Ggr, Format::Csv] {
+            let read = parse(format, "round trip", &write(format, &palette)).unwrap();
+            for (a, b) in palette.sample(64).iter().zip(read.sample(64)) {
+                let close = |x: u8, y: u8| x.abs_diff(y) <= 1;
+                assert!(close(a.r, b.r) && close(a.g, b.g) && close(a.b, b.b), "{} changed {a:?} to {b:?}", format.extension());
+            }
+        }
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        assert!(line(parse(Format::Csv, "", "position,r,g,b\n0,255,0,0\n\n0.5,12,x,0\n")) == 4);
+        assert!(line(parse(Format::Map, "", "0 0 0\n; comment\n12 34\n")) == 3);
+        assert!(line(parse(Format::Ggr, "", "GIMP Gradient\n2\n0 0.5 1 1 0 0 1 0 0 1 1 0 0\n0 0.5 1 1 0 0\n")) == 4);
+        assert!(line(parse(Format::Ugr, "", "a {\ngradient:\n  index=0 color=255\n  index=100\n}\n")) == 4);
+    }
+}
@@ -0,0 +1,66 @@
+pub mod file;
+pub mod palette;
+
+use sfml::graphics::Color;
+use std::ops::Rem;
+
+/// cumulative distribution of the values of a frame
+///
+/// mapping every value to the share of values under it spreads the colors evenly over the pixels,
+/// whatever the depth
+pub struct Histogram {
+    sorted: Vec<f64>,
+}
+
+impl Histogram {
+    /// NaNs are left out
+    pub fn new(values: impl Iterator<Item = f64>) -> Self {
+        let mut sorted: Vec<_> = values.filter(|x| !x.is_nan()).collect();
+        sorted.sort_unstable_by(f64::total_cmp);
+        Histogram { sorted }
+    }
+
+    /// share of the values smaller than `value`, in [0, 1]
+    pub fn rank(&self, value: f64) -> f64 {
+        if self.sorted.is_empty() {
+            return 0.;
+        }
+        self.sorted.partition_point(|&x| x < value) as f64 / self.sorted.len() as f64
+    }
+}
+
+/// `a` at 0 to `b` at 1
+pub fn mix(a: Color, b: Color, t: f64) -> Color {
+    let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64)*t).round() as u8;
+    Color::rgba(channel(a.r, b.r), channel(a.g, b.g), channel(a.b, b.b), channel(a.a, b.a))
+}
+
+pub fn hsv_to_rgb(h: f64, s: f64, v: f64) -> Color {
+    //360, 1, 1
+    let h = h.rem(360.);
+    let c = v * s;
+    let x = c * 1. - (((h / 60.).rem_euclid(2.)) - 1.).abs();
+    let m = v - c;
+
+    let (r, g, b) = {
+        if h < 60. {
+            (c, x, 0.)
+        } else if h < 120. {
+            (x, c, 0.)
+        } else if h < 180. {
+            (0., c, x)
+        } else if h < 240. {
+            (0., x, c)
+        } else if h < 300. {
+            (x, 0., c)
+        } else {
+            (c, 0., x)
+        }
+    };
+
+    Color::rgb(
+        ((r + m) * 256.) as u8,
+        ((g + m) * 256.) as u8,
+        ((b + m) * 256.) as u8,
+    )
+}
@@ -0,0 +1,122 @@
+use sfml::graphics::Color;
+use std::f64::consts::PI;
+
+use super::{hsv_to_rgb, mix};
+
+/// color at a position of a gradient
+#[derive(Clone, Copy)]
+pub struct Stop {
+    /// in [0, 1]
+    pub position: f64,
+    pub color: Color,
+}
+
+/// how a palette makes its colors
+#[derive(Clone)]
+pub enum Kind {
+    /// `a + b*cos(2π(c*t + d))` for every channel, in [0, 1]
+    Cosine {a: [f64; 3], b: [f64; 3], c: [f64; 3], d: [f64; 3]},
+    /// linear between the stops, sorted by position, the last one goes back to the first
+    Gradient(Vec<Stop>),
+    /// evenly spaced colors, linear in between and wrapping around
+    Lut(Vec<Color>),
+}
+
+/// maps a palette index to a color, gradients, tables and cosines with whole `c` repeat every
+/// unit of index
+#[derive(Clone)]
+pub struct Palette {
+    pub name: String,
+    pub kind: Kind,
+}
+
+impl Palette {
+    pub fn cosine(name: &str, a: [f64; 3], b: [f64; 3], c: [f64; 3], d: [f64; 3]) -> Self {
+        Palette {name: name.to_string(), kind: Kind::Cosine {a, b, c, d}}
+    }
+
+    /// the stops get sorted, stops at the same position keep their order and make a sharp edge
+    pub fn gradient(name: &str, mut stops: Vec<Stop>) -> Self {
+        for stop in &mut stops {
+            stop.position = stop.position.clamp(0., 1.);
+        }
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+        Palette {name: name.to_string(), kind: Kind::Gradient(stops)}
+    }
+
+    pub fn lut(name: &str, colors: Vec<Color>) -> Self {
+        Palette {name: name.to_string(), kind: Kind::Lut(colors)}
+    }
+
+    pub fn color(&self, t: f64) -> Color {
+        // NaN would index out of the tables
+        if !t.is_finite() {
+            return Color::BLACK;
+        }
+        match &self.kind {
+            Kind::Cosine {a, b, c, d} => {
+                let channel = |i: usize| ((a[i] + b[i]*((c[i]*t + d[i])*2.*PI).cos()).clamp(0., 1.)*255.).round() as u8;
+                Color::rgb(channel(0), channel(1), channel(2))
+            }
+            Kind::Gradient(stops) => gradient(stops, t.rem_euclid(1.)),
+            Kind::Lut(colors) => {
+                if colors.is_empty() {
+                    return Color::BLACK;
+                }
+                let x = t.rem_euclid(1.)*colors.len() as f64;
+                let i = (x as usize).min(colors.len() - 1);
+                mix(colors[i], colors[(i + 1) % colors.len()], x - i as f64)
+            }
+        }
+    }
+
+    /// the colors at `count` evenly spaced positions from 0
+    pub fn sample(&self, count: usize) -> Vec<Color> {
+        (0..count).map(|i| self.color(i as f64 / count as f64)).collect()
+    }
+}
+
+/// `t` in [0, 1)
+fn gradient(stops: &[Stop], t: f64) -> Color {
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Color::BLACK,
+    };
+    // first stop past t, the ends wrap around to the other side
+    let i = stops.partition_point(|s| s.position <= t);
+    let (a, a_position, b, b_position) = if i == 0 {
+        (last, last.position - 1., first, first.position)
+    } else if i == stops.len() {
+        (last, last.position, first, first.position + 1.)
+    } else {
+        (&stops[i - 1], stops[i - 1].position, &stops[i], stops[i].position)
+    };
+    let f = if b_position > a_position {(t - a_position) / (b_position - a_position)} else {0.};
+    mix(a.color, b.color, f)
+}
+
+/// palettes `P` cycles through
+pub fn presets() -> Vec<Palette> {
+    let stop = |position: f64, r: u8, g: u8, b: u8| Stop {position, color: Color::rgb(r, g, b)};
+    vec![
+        Palette::cosine("classic", [0.5; 3], [0.5; 3], [1.; 3], [0., 0.1, 0.2]),
+        Palette::cosine("rainbow", [0.5; 3], [0.5; 3], [1.; 3], [0., 0.33, 0.67]),
+        Palette::cosine("dusk", [0.5; 3], [0.5; 3], [2., 1., 0.], [0.5, 0.2, 0.25]),
+        Palette::gradient("fire", vec![
+            stop(0., 0, 0, 0),
+            stop(0.3, 180, 20, 0),
+            stop(0.55, 255, 140, 0),
+            stop(0.75, 255, 240, 120),
+            stop(0.85, 255, 255, 255),
+        ]),
+        Palette::gradient("ocean", vec![
+            stop(0., 0, 7, 100),
+            stop(0.16, 32, 107, 203),
+            stop(0.42, 237, 255, 255),
+            stop(0.64, 255, 170, 0),
+            stop(0.86, 0, 2, 0),
+        ]),
+        Palette::gradient("grey", vec![stop(0., 0, 0, 0), stop(0.5, 255, 255, 255)]),
+        Palette::lut("hsv", (0..64).map(|i| hsv_to_rgb(i as f64*360./64., 0.8, 0.8)).collect()),
+    ]
+}
@@ -1,92 +1,446 @@
-use std::thread;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
 use std::time::{Instant, Duration};
 
-use std::f64::consts::SQRT_2;
-// use std::f64::consts::PI;
+use std::f64::consts::PI;
 
 use sfml;
 use sfml::system::Vector2f;
 use sfml::window::{Key, Event, mouse};
-use sfml::graphics::{Color, Image, Rect, RectangleShape, RenderTarget, RenderTexture, Shape, Sprite, Texture, Transformable};
+use sfml::graphics::{Color, Image, Rect, RenderTarget, Sprite, Texture, Transformable};
 
 pub mod mandel;
 use mandel::cplx::{self, Cplx};
 use mandel::Mandel;
-
-// use mandel::big_float;
+use mandel::float::Float;
+use mandel::big_float::{BigFloat, FloatExp, MAX_LIMBS};
+use mandel::double_double::{DoubleDouble, QuadDouble};
+use mandel::perturbation::Reference;
+use mandel::simd;
+use mandel::trap::{Trap, TrapShape};
 
 pub mod colors;
 
-fn pos_to_cplx(x:i32, y:i32, config: &Config) -> cplx::Cplx<f64> {
-    // width: usize, height: usize, zoom:f64, offset: cplx::Cplx<f64>
+pub mod pool;
+use pool::{CancelToken, Generations, Pool, Worker};
+use colors::Histogram;
+use colors::palette::{self, Palette};
+use colors::file;
+
+/// f32 is used while a pixel is at least this many f32 ulps wide at the coordinates of the view
+///
+/// the sample points are then off by less than 1/8192 pixel. where the smooth count changes by
+/// less than an iteration from a pixel to its neighbours in f64, the f32 one stays within a
+/// quarter of that change. near the boundary rounding grows with the iterations like any offset
+/// would: at 256 iterations under 0.2% of the pixels flip between inside and outside and under 2%
+/// of the escaping ones are off by more than half an iteration, about 3% at 4096
+const F32_PIXEL_ULPS: f64 = 4096.;
+/// past this zoom f64 can't tell neighbouring pixels apart, pixels are computed relative to a reference orbit
+const PERTURBATION_ZOOM: FloatExp = FloatExp::exp2(40);
+/// the reference needs about log2(zoom) + 32 bits to place pixels, double-double holds ~104
+const DOUBLE_DOUBLE_ZOOM: FloatExp = FloatExp::exp2(70);
+/// quad-double holds ~208 bits
+const QUAD_DOUBLE_ZOOM: FloatExp = FloatExp::exp2(170);
+/// pixel offsets underflow f64 around 2^1000, the derivative can grow past the zoom itself so switch well before
+const FLOATEXP_ZOOM: FloatExp = FloatExp::exp2(900);
+/// deepest zoom, past it `precision` asks for more bits than a BigFloat holds
+const MAX_ZOOM: FloatExp = FloatExp::exp2((MAX_LIMBS*64 - 64) as i64);
+
+/// offset of the pixel from the center of the view
+fn pos_to_delta(x:i32, y:i32, config: &Config) -> cplx::Cplx<FloatExp> {
+    // width: usize, height: usize, zoom:FloatExp
     let min = std::cmp::min(config.size.0, config.size.1) as f64;
     cplx::Cplx{
-        re: ((x-config.size.0 as i32/2) as f64)/min/config.zoom,
-        im: ((y-config.size.1 as i32/2) as f64)/min/config.zoom,
-    } + config.offset
+        re: FloatExp::from(((x-config.size.0 as i32/2) as f64)/min)/config.zoom,
+        im: FloatExp::from(((y-config.size.1 as i32/2) as f64)/min)/config.zoom,
+    }
 }
 
-fn cplx_to_pos(z:cplx::Cplx<f64>, config: &Config) -> Vector2f {
+fn pos_to_cplx<T: Float>(x:i32, y:i32, config: &Config) -> cplx::Cplx<T> {
+    Cplx::<T>::from_floatexp(pos_to_delta(x, y, config)) + Cplx::<T>::from_big(&config.offset)
+}
+
+fn delta_to_pos(d:cplx::Cplx<FloatExp>, config: &Config) -> Vector2f {
     let min = std::cmp::min(config.size.0, config.size.1) as f64;
     Vector2f{
-        x: ((z.re - config.offset.re)*config.zoom*min) as f32 + (config.size.0/2) as f32,
-        y: ((z.im - config.offset.im)*config.zoom*min) as f32 + (config.size.1/2) as f32,
+        x: ((d.re*config.zoom).to_f64()*min) as f32 + (config.size.0/2) as f32,
+        y: ((d.im*config.zoom).to_f64()*min) as f32 + (config.size.1/2) as f32,
     }
 }
 
+fn cplx_to_pos<T: Float>(z:cplx::Cplx<T>, config: &Config) -> Vector2f {
+    delta_to_pos((z - Cplx::<T>::from_big(&config.offset)).to_floatexp(), config)
+}
 
-fn area(tx: mpsc::Sender<(usize, usize, Mandel)>, rect: sfml::graphics::Rect<usize>, config: Config) {
-    let tx = tx.clone();
-    let mut closed = true;
-    let mut calculate_and_send = |x, y| {
-        let mut m = Mandel::new(pos_to_cplx(x as i32, y as i32, &config), config.iter_max);
-        m.calculate_mandel_smooth();
-        if m.get_finished().unwrap().is_finite() {closed = false;}
-        if let Err(_) = tx.send((x, y, m)) {return false;}
-        true
-    };
-    for x in rect.left..rect.left+rect.width {
-        if  !calculate_and_send(x, rect.top) ||
-            !calculate_and_send(x, rect.top+rect.height-1) {
-            return;
+/// bits needed for the offset to place every pixel at the current zoom
+fn precision(config: &Config) -> usize {
+    config.zoom.log2().max(0.) as usize + 64
+}
+
+/// moves the view center by `d`, keeping enough precision for the current zoom
+fn move_offset(config: &mut Config, d: cplx::Cplx<FloatExp>) {
+    let bits = precision(config);
+    let offset = Cplx{re: config.offset.re.with_prec(bits), im: config.offset.im.with_prec(bits)};
+    // the sum keeps the precision of the offset
+    config.offset = offset + Cplx::<BigFloat>::from_floatexp(d);
+}
+
+/// width of a pixel in the plane
+fn pixel_size(config: &Config) -> FloatExp {
+    let min = std::cmp::min(config.size.0, config.size.1) as f64;
+    FloatExp::from(1. / min) / config.zoom
+}
+
+/// pixels are wide enough for f32 to tell them apart, see `F32_PIXEL_ULPS`
+fn f32_resolves(config: &Config) -> bool {
+    let min = std::cmp::min(config.size.0, config.size.1) as f64;
+    let pixel = 1. / (config.zoom.to_f64() * min);
+    let corner = pos_to_delta(0, 0, config).to_f64();
+    let offset = config.offset.to_f64();
+    // biggest coordinate in view, the ulp grows with it
+    let max = (offset.re.abs() + corner.re.abs()).max(offset.im.abs() + corner.im.abs());
+    pixel >= F32_PIXEL_ULPS * max * f32::EPSILON as f64
+}
+
+fn compute(x: usize, y: usize, config: &Config, reference: &Option<Arc<Reference>>) -> Mandel {
+    let mut m = Mandel::new(pos_to_cplx(x as i32, y as i32, config), config.iter_max, pixel_size(config).to_f64(), config.trap, config.averages);
+    match reference {
+        Some(reference) if config.zoom > FLOATEXP_ZOOM => m.calculate_perturbed(reference, pos_to_delta(x as i32, y as i32, config)),
+        Some(reference) => m.calculate_perturbed(reference, pos_to_delta(x as i32, y as i32, config).to_f64()),
+        None => m.calculate_mandel_smooth(),
+    }
+    m
+}
+
+/// pixels computed between two checks for cancellation, a multiple of every SIMD width
+const CANCEL_CHECK_PIXELS: usize = 64;
+
+/// the pixels at `points`, several at a time in SIMD lanes when no reference orbit is needed
+///
+/// stops early once the render is cancelled, fewer results than points come back then
+fn compute_points(points: &[(usize, usize)], render: &Render) -> Vec<Mandel> {
+    let Render {config, precision, reference, ..} = render;
+    let pixel = pixel_size(config).to_f64();
+    let mut result = Vec::with_capacity(points.len());
+    for points in points.chunks(CANCEL_CHECK_PIXELS) {
+        if render.cancelled() {
+            break;
+        }
+        match precision {
+            Precision::F32 => {
+                let mut ms: Vec<_> = points.iter().map(|&(x, y)| Mandel::<f32>::new(pos_to_cplx(x as i32, y as i32, config), config.iter_max, pixel, config.trap, config.averages)).collect();
+                simd::calculate_mandel_smooth(&mut ms);
+                result.extend(ms.iter().map(Mandel::to_f64));
+            }
+            Precision::F64 => {
+                let mut ms: Vec<_> = points.iter().map(|&(x, y)| Mandel::new(pos_to_cplx(x as i32, y as i32, config), config.iter_max, pixel, config.trap, config.averages)).collect();
+                simd::calculate_mandel_smooth(&mut ms);
+                result.extend(ms);
+            }
+            _ => result.extend(points.iter().map(|&(x, y)| compute(x, y, config, reference))),
         }
     }
-    for y in rect.top..rect.top+rect.height {
-        if  !calculate_and_send(rect.left, y) ||
-            !calculate_and_send(rect.left+rect.width-1, y) {
-            return;
+    result
+}
+
+/// every pixel of `rect` row by row, `None` when the render got cancelled on the way
+///
+/// pixels already known are `None`, they're drawn already
+fn compute_tile(rect: Rect<usize>, render: &Render) -> Option<Vec<Option<Mandel>>> {
+    let points: Vec<_> = (rect.top..rect.top+rect.height)
+        .flat_map(|y| (rect.left..rect.left+rect.width).map(move |x| (x, y)))
+        .collect();
+    let missing: Vec<_> = points.iter().copied().filter(|&(x, y)| render.known.get(x, y).is_none()).collect();
+    let mut computed = compute_points(&missing, render);
+    if computed.len() < missing.len() {
+        return None;
+    }
+    computed.reverse();
+    Some(points.iter().map(|&(x, y)| match render.known.get(x, y) {
+        Some(_) => None,
+        None => computed.pop(),
+    }).collect())
+}
+
+/// strides of the coarse passes before the full one, each sample is drawn as a `step`×`step` block
+const PASSES: [usize; 3] = [8, 4, 2];
+
+const UNKNOWN: u8 = 0;
+const ESCAPED: u8 = 1;
+const INSIDE: u8 = 2;
+
+/// pixels whose result exists already, carried over from the previous view or computed by a
+/// coarse pass, the jobs don't compute them again
+struct Known {
+    width: usize,
+    cells: Vec<AtomicU8>,
+}
+
+impl Known {
+    fn new(size: (usize, usize)) -> Self {
+        Known {width: size.0, cells: (0..size.0*size.1).map(|_| AtomicU8::new(UNKNOWN)).collect()}
+    }
+
+    /// `Some(escaped)` for a pixel already computed
+    fn get(&self, x: usize, y: usize) -> Option<bool> {
+        match self.cells[y*self.width + x].load(Ordering::Relaxed) {
+            ESCAPED => Some(true),
+            INSIDE => Some(false),
+            _ => None,
         }
     }
 
-    if !closed {
-        if rect.width < 128 || rect.height < 128 {
-            for x in rect.left+1..rect.left+rect.width-1 {
-                for y in rect.top+1..rect.top+rect.height-1 {
-                    let mut m = Mandel::new(pos_to_cplx(x as i32, y as i32, &config), config.iter_max);
-                    m.calculate_mandel_smooth();
-                    if let Err(_) = tx.send((x, y, m)) {return;}
-                }
+    fn set(&self, x: usize, y: usize, m: &Mandel) {
+        let escaped = m.get_finished().unwrap().is_finite();
+        self.cells[y*self.width + x].store(if escaped {ESCAPED} else {INSIDE}, Ordering::Relaxed);
+    }
+}
+
+/// results for a rectangle of pixels, row by row, sent as one message
+struct Tile {
+    generation: u64,
+    rect: Rect<usize>,
+    /// one result every `step` pixels on both axes, drawn as a `step`×`step` block.
+    /// 1 for the full pass
+    step: usize,
+    /// `None` where the result is known already
+    pixels: Vec<Option<Mandel>>,
+}
+
+/// what every rectangle of one render needs, cloned into the jobs
+#[derive(Clone)]
+struct Render {
+    tx: mpsc::Sender<Tile>,
+    config: Config,
+    precision: Precision,
+    reference: Option<Arc<Reference>>,
+    known: Arc<Known>,
+    /// cancelled when a newer render starts, the jobs left then stop within a few pixels
+    token: CancelToken,
+}
+
+impl Render {
+    #[inline]
+    fn cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// false once the render is cancelled or nobody listens anymore
+    fn send(&self, rect: Rect<usize>, step: usize, pixels: Vec<Option<Mandel>>) -> bool {
+        // a side of a 2 pixels high rectangle
+        if pixels.is_empty() {
+            return !self.cancelled();
+        }
+        !self.cancelled() && self.tx.send(Tile {generation: self.token.generation(), rect, step, pixels}).is_ok()
+    }
+}
+
+/// coarse pass `PASSES[index]`, one job per row of samples, the last job to finish starts the
+/// next pass. after the last coarse pass comes the full one
+fn pass(render: Render, index: usize, worker: &Worker) {
+    let (width, height) = render.config.size;
+    let Some(&step) = PASSES.get(index) else {
+        area(render, Rect{left: 0, top: 0, width, height}, worker);
+        return;
+    };
+    let rows: Vec<_> = (0..height).step_by(step).collect();
+    let remaining = Arc::new(AtomicUsize::new(rows.len()));
+    // the last job spawned runs first
+    for y in rows.into_iter().rev() {
+        let render = render.clone();
+        let remaining = remaining.clone();
+        worker.spawn(move |worker| {
+            coarse_row(&render, index, y);
+            if remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                pass(render, index + 1, worker);
             }
-            return;
+        });
+    }
+}
+
+/// samples of the row `y` of pass `PASSES[index]` that aren't known yet
+fn coarse_row(render: &Render, index: usize, y: usize) {
+    let step = PASSES[index];
+    let (width, height) = render.config.size;
+    let done = |x: usize| index > 0 && x.is_multiple_of(2*step) && y.is_multiple_of(2*step);
+    let fresh: Vec<_> = (0..width).step_by(step).map(|x| !done(x) && render.known.get(x, y).is_none()).collect();
+    let points: Vec<_> = (0..width).step_by(step).zip(&fresh).filter(|(_, &f)| f).map(|(x, _)| (x, y)).collect();
+    let mut computed = compute_points(&points, render);
+    if computed.len() < points.len() {
+        return;
+    }
+    for (&(x, y), m) in points.iter().zip(&computed) {
+        render.known.set(x, y, m);
+    }
+    computed.reverse();
+    let pixels = fresh.iter().map(|&f| if f {computed.pop()} else {None}).collect();
+    render.send(Rect{left: 0, top: y, width, height: step.min(height - y)}, step, pixels);
+}
+
+/// rows computed by one job in a rectangle that isn't split any further
+const BAND_ROWS: usize = 16;
+
+/// every row of `rect`, sent one by one
+fn rows(render: &Render, rect: Rect<usize>) {
+    for y in rect.top..rect.top+rect.height {
+        let row = Rect{top: y, height: 1, ..rect};
+        let Some(pixels) = compute_tile(row, render) else {return};
+        if !render.send(row, 1, pixels) {return;}
+    }
+}
+
+/// the pixels of `rect` not known yet, as inside the set without iterating them, in one tile
+fn fill(render: &Render, rect: Rect<usize>) {
+    let config = &render.config;
+    let pixels = (rect.top..rect.top+rect.height)
+        .flat_map(|y| (rect.left..rect.left+rect.width).map(move |x| (x, y)))
+        .map(|(x, y)| render.known.get(x, y).is_none().then(|| Mandel::new_filled(pos_to_cplx(x as i32, y as i32, config), config.iter_max)))
+        .collect();
+    render.send(rect, 1, pixels);
+}
+
+/// every row of `rect`, one job per band of `BAND_ROWS` rows
+fn bands(render: &Render, rect: Rect<usize>, worker: &Worker) {
+    for top in (rect.top..rect.top+rect.height).step_by(BAND_ROWS) {
+        let band = Rect{top, height: BAND_ROWS.min(rect.top+rect.height-top), ..rect};
+        let render = render.clone();
+        worker.spawn(move |_| rows(&render, band));
+    }
+}
+
+/// Mariani-Silver: computes the border of `rect`, fills it if the border is all inside the set,
+/// otherwise splits it in two jobs, or in bands of rows when the distance estimate shows it's
+/// clear of the set
+///
+/// inside the set the pixels only have colors of their own with an interior coloring or a trap,
+/// the filled rectangle is computed in bands then. otherwise its pixels are sent as inside, the
+/// blocks of the coarse passes there can come from samples outside
+fn area(render: Render, rect: sfml::graphics::Rect<usize>, worker: &Worker) {
+    if render.cancelled() {
+        return;
+    }
+    let mut closed = true;
+    // when the lower bound of the distance to the set reaches across the whole rectangle from
+    // one of its border pixels there's no point of the set in it
+    let diagonal = pixel_size(&render.config) * FloatExp::from((rect.width as f64).hypot(rect.height as f64));
+    let mut clear = false;
+    let sides = [
+        Rect{left: rect.left, top: rect.top, width: rect.width, height: 1},
+        Rect{left: rect.left, top: rect.top+rect.height-1, width: rect.width, height: 1},
+        Rect{left: rect.left, top: rect.top+1, width: 1, height: rect.height.saturating_sub(2)},
+        Rect{left: rect.left+rect.width-1, top: rect.top+1, width: 1, height: rect.height.saturating_sub(2)},
+    ];
+    for side in sides {
+        let Some(pixels) = compute_tile(side, &render) else {return};
+        let escaped = |i: usize, m: &Option<Mandel>| match m {
+            Some(m) => m.get_finished().unwrap().is_finite(),
+            None => render.known.get(side.left + i%side.width, side.top + i/side.width).unwrap(),
+        };
+        if pixels.iter().enumerate().any(|(i, m)| escaped(i, m)) {closed = false;}
+        let far = |m: &Option<Mandel>| m.and_then(|m| m.get_distance_bound()).is_some_and(|d| d >= diagonal);
+        if pixels.iter().any(far) {clear = true;}
+        if !render.send(side, 1, pixels) {return;}
+    }
+
+    let inner = Rect{left: rect.left+1, top: rect.top+1, width: rect.width.saturating_sub(2), height: rect.height.saturating_sub(2)};
+    if closed {
+        if render.config.coloring.interior != InteriorMode::Black || render.config.trap.is_some() {
+            bands(&render, inner, worker);
+        } else {
+            fill(&render, inner);
         }
+        return;
+    }
+    if rect.width < 128 || rect.height < 128 {
+        rows(&render, inner);
+        return;
+    }
+    if clear {
+        // nothing to fill anywhere inside, bands without borders of their own
+        bands(&render, inner, worker);
+        return;
+    }
 
-        let tx1 = tx.clone();
-        let tx2 = tx.clone();
-        if rect.width > rect.height {
-            thread::spawn(move || {
-                area(tx1, sfml::graphics::Rect::<usize>{left:rect.left+1, top:rect.top+1, width: rect.width/2, height: rect.height-2}, config);
-            });
-            thread::spawn(move || {
-                area(tx2, sfml::graphics::Rect::<usize>{left:rect.left+rect.width/2, top:rect.top+1, width: rect.width/2, height: rect.height-2}, config);
-            });
+    let (rect1, rect2) = if rect.width > rect.height {
+        (sfml::graphics::Rect::<usize>{left:rect.left+1, top:rect.top+1, width: rect.width/2, height: rect.height-2},
+         sfml::graphics::Rect::<usize>{left:rect.left+rect.width/2, top:rect.top+1, width: rect.width/2, height: rect.height-2})
+    } else {
+        (sfml::graphics::Rect::<usize>{left:rect.left+1, top:rect.top+1, width: rect.width-2, height: rect.height/2},
+         sfml::graphics::Rect::<usize>{left:rect.left+1, top:rect.top+rect.height/2, width: rect.width-2, height: rect.height/2})
+    };
+    let render1 = render.clone();
+    worker.spawn(move |worker| area(render1, rect1, worker));
+    worker.spawn(move |worker| area(render, rect2, worker));
+}
+
+/// counters over the pixels of one render
+#[derive(Clone, Copy, Default)]
+struct RenderStats {
+    pixels: usize,
+    glitched: usize,
+    corrected: usize,
+    skipped: usize,
+    /// interior pixels whose orbit was caught in a cycle
+    periodic: usize,
+}
+
+impl RenderStats {
+    fn add(&mut self, m: &Mandel) {
+        self.pixels += 1;
+        if m.get_glitched() {self.glitched += 1;}
+        if m.get_corrected() {self.corrected += 1;}
+        self.skipped += m.get_skipped();
+        if m.get_period().is_some() {self.periodic += 1;}
+    }
+}
+
+/// number type used for the reference orbit, f32 and f64 mean no reference and pixels computed directly with that type
+#[derive(Clone, Copy, PartialEq)]
+enum Precision {
+    F32,
+    F64,
+    DoubleDouble,
+    QuadDouble,
+    BigFloat,
+}
+
+impl Precision {
+    fn pick(config: &Config) -> Self {
+        if f32_resolves(config) {
+            Precision::F32
+        } else if config.zoom <= PERTURBATION_ZOOM {
+            Precision::F64
+        } else if config.zoom <= DOUBLE_DOUBLE_ZOOM {
+            Precision::DoubleDouble
+        } else if config.zoom <= QUAD_DOUBLE_ZOOM {
+            Precision::QuadDouble
         } else {
-            thread::spawn(move || {
-                area(tx1, sfml::graphics::Rect::<usize>{left:rect.left+1, top:rect.top+1, width: rect.width-2, height: rect.height/2}, config);
-            });
-            thread::spawn(move || {
-                area(tx2, sfml::graphics::Rect::<usize>{left:rect.left+1, top:rect.top+rect.height/2, width: rect.width-2, height: rect.height/2}, config);
-            });
+            Precision::BigFloat
+        }
+    }
+
+    /// `None` for the plain float precisions, or when `token` is cancelled before it's done
+    fn reference(&self, config: &Config, token: &CancelToken) -> Option<Reference> {
+        // the corners are the farthest from the center
+        let radius = pos_to_delta(0, 0, config).abs();
+        let cancelled = || token.is_cancelled();
+        match self {
+            Precision::F32 | Precision::F64 => None,
+            Precision::DoubleDouble => Reference::new::<DoubleDouble>(config.offset, config.iter_max, radius, cancelled),
+            Precision::QuadDouble => Reference::new::<QuadDouble>(config.offset, config.iter_max, radius, cancelled),
+            Precision::BigFloat => Reference::new::<BigFloat>(config.offset, config.iter_max, radius, cancelled),
+        }
+    }
+}
+
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Precision::F32 => write!(f, "f32"),
+            Precision::F64 => write!(f, "f64"),
+            Precision::DoubleDouble => write!(f, "double-double"),
+            Precision::QuadDouble => write!(f, "quad-double"),
+            Precision::BigFloat => write!(f, "BigFloat"),
         }
     }
 }
@@ -94,14 +448,238 @@ fn area(tx: mpsc::Sender<(usize, usize, Mandel)>, rect: sfml::graphics::Rect<usi
 #[derive(Clone, Copy)]
 struct Config {
     pub size: (usize, usize),
-    pub zoom: f64,
-    pub offset: Cplx<f64>,
+    /// f64 would overflow past 2^1023
+    pub zoom: FloatExp,
+    pub offset: Cplx<BigFloat>,
     pub iter_max: usize,
     pub redraw: bool,
     pub debug: bool,
     pub aa: usize,
+    /// render threads, 0 for one per core
+    pub threads: usize,
+    /// orbit trap the pixels measure their orbit against, changing it means computing again
+    pub trap: Option<Trap>,
+    /// the pixels sum the averages of the orbit, the average colorings need them
+    pub averages: bool,
+    pub coloring: Coloring,
+    /// the coloring changed, the stored results are colored again
+    pub recolor: bool,
+    /// the palette is written to a file, see `EXPORT_FORMAT`
+    pub export: bool,
+}
+
+/// format `S` writes the palette in, to `<name>.<extension>` in the working directory
+const EXPORT_FORMAT: file::Format = file::Format::Ggr;
+
+/// what the color of a pixel is made of
+#[derive(Clone, Copy, PartialEq)]
+enum ColorMode {
+    /// palette lit by the light
+    Shaded,
+    Palette,
+    /// light only, in grey
+    Relief,
+    /// palette by the distance to the set in pixels
+    Distance,
+}
+
+impl ColorMode {
+    fn next(self) -> Self {
+        match self {
+            ColorMode::Shaded => ColorMode::Palette,
+            ColorMode::Palette => ColorMode::Relief,
+            ColorMode::Relief => ColorMode::Distance,
+            ColorMode::Distance => ColorMode::Shaded,
+        }
+    }
+}
+
+impl std::fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ColorMode::Shaded => write!(f, "shaded"),
+            ColorMode::Palette => write!(f, "palette"),
+            ColorMode::Relief => write!(f, "relief"),
+            ColorMode::Distance => write!(f, "distance"),
+        }
+    }
+}
+
+/// what the palette is indexed with for the points that escape
+#[derive(Clone, Copy, PartialEq)]
+enum Algorithm {
+    /// smooth iteration count
+    Smooth,
+    /// triangle inequality average
+    Triangle,
+    Stripe,
+    Curvature,
+}
+
+impl Algorithm {
+    fn next(self) -> Self {
+        match self {
+            Algorithm::Smooth => Algorithm::Triangle,
+            Algorithm::Triangle => Algorithm::Stripe,
+            Algorithm::Stripe => Algorithm::Curvature,
+            Algorithm::Curvature => Algorithm::Smooth,
+        }
+    }
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Algorithm::Smooth => write!(f, "smooth"),
+            Algorithm::Triangle => write!(f, "triangle inequality"),
+            Algorithm::Stripe => write!(f, "stripe"),
+            Algorithm::Curvature => write!(f, "curvature"),
+        }
+    }
+}
+
+/// shape of the mapping from the smooth iteration count to the palette
+#[derive(Clone, Copy, PartialEq)]
+enum Function {
+    Linear,
+    Sqrt,
+    Cbrt,
+    Log,
+    LogLog,
+    /// with its exponent
+    Power(f64),
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Function::Linear => write!(f, "linear"),
+            Function::Sqrt => write!(f, "sqrt"),
+            Function::Cbrt => write!(f, "cbrt"),
+            Function::Log => write!(f, "log"),
+            Function::LogLog => write!(f, "log log"),
+            Function::Power(exponent) => write!(f, "power {exponent:.2}"),
+        }
+    }
+}
+
+/// `density*function(n) + phase`, the palette index of a smooth iteration count `n`
+#[derive(Clone, Copy)]
+struct Transfer {
+    function: Function,
+    density: f64,
+    phase: f64,
 }
 
+impl Transfer {
+    fn apply(&self, n: f64) -> f64 {
+        // far from the set n goes a bit under 0
+        let n = n.max(0.);
+        let x = match self.function {
+            Function::Linear => n,
+            Function::Sqrt => n.sqrt(),
+            Function::Cbrt => n.cbrt(),
+            Function::Log => n.ln_1p(),
+            Function::LogLog => n.ln_1p().ln_1p(),
+            Function::Power(exponent) => n.powf(exponent),
+        };
+        self.density*x + self.phase
+    }
+}
+
+/// the transfers `F` cycles through, each keeps its own density and phase
+const TRANSFERS: [Transfer; 6] = [
+    Transfer {function: Function::Sqrt, density: 0.5, phase: 3.3},
+    Transfer {function: Function::Linear, density: 1./32., phase: 0.},
+    Transfer {function: Function::Cbrt, density: 1., phase: 0.},
+    Transfer {function: Function::Log, density: 1., phase: 0.},
+    Transfer {function: Function::LogLog, density: 4., phase: 0.},
+    Transfer {function: Function::Power(0.75), density: 0.1, phase: 0.},
+];
+
+/// density step of `Up` and `Down`
+const DENSITY_STEP: f64 = 1.25;
+/// phase step of `Comma` and `Period`, a twentieth of the cosine palette
+const PHASE_STEP: f64 = 0.05;
+/// exponent step of `Semicolon` and `Quote`
+const EXPONENT_STEP: f64 = 0.05;
+
+/// the traps `T` cycles through, `C` moves the current one to the cursor
+const TRAPS: [Trap; 5] = [
+    Trap {shape: TrapShape::Point, center: Cplx{re: -0.5, im: 0.5}, size: 0.5, angle: 0.},
+    Trap {shape: TrapShape::Line, center: Cplx{re: 0., im: 0.5}, size: 0.5, angle: 0.},
+    Trap {shape: TrapShape::Cross, center: Cplx{re: 0., im: 0.}, size: 0.5, angle: PI/4.},
+    Trap {shape: TrapShape::Circle, center: Cplx{re: 0.25, im: 0.}, size: 0.5, angle: 0.},
+    Trap {shape: TrapShape::Stalk, center: Cplx{re: 0., im: 0.}, size: 0.05, angle: 0.},
+];
+
+/// color of the points that don't escape
+#[derive(Clone, Copy, PartialEq)]
+enum InteriorMode {
+    Black,
+    /// how strongly the cycle attracts, the size of its multiplier
+    Magnitude,
+    /// hue by the angle of the multiplier
+    Angle,
+    /// palette by the distance to the boundary of the component in pixels
+    Distance,
+    /// hue by the period of the cycle
+    Period,
+}
+
+impl InteriorMode {
+    fn next(self) -> Self {
+        match self {
+            InteriorMode::Black => InteriorMode::Magnitude,
+            InteriorMode::Magnitude => InteriorMode::Angle,
+            InteriorMode::Angle => InteriorMode::Distance,
+            InteriorMode::Distance => InteriorMode::Period,
+            InteriorMode::Period => InteriorMode::Black,
+        }
+    }
+}
+
+impl std::fmt::Display for InteriorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InteriorMode::Black => write!(f, "black"),
+            InteriorMode::Magnitude => write!(f, "multiplier"),
+            InteriorMode::Angle => write!(f, "angle"),
+            InteriorMode::Distance => write!(f, "distance"),
+            InteriorMode::Period => write!(f, "period"),
+        }
+    }
+}
+
+
+/// settings that only change the colors, not the results
+#[derive(Clone, Copy)]
+struct Coloring {
+    mode: ColorMode,
+    algorithm: Algorithm,
+    /// starts as `TRANSFERS`, changed from the keyboard
+    transfers: [Transfer; TRANSFERS.len()],
+    /// index in `transfers`
+    transfer: usize,
+    interior: InteriorMode,
+    /// index in the palettes, taken modulo their number
+    palette: usize,
+    /// direction the light comes from, radians
+    light: f64,
+    /// darkens what's closer to the set than `OUTLINE_PIXELS`, thin filaments show up then
+    outline: bool,
+    /// how much of the trap color goes in, 0 for none and 1 for only the trap
+    trap_blend: f64,
+    /// colors spread evenly over the pixels of the frame, once it's done
+    equalize: bool,
+}
+
+/// palette cycles over the whole frame when it's equalized
+const EQUALIZED_CYCLES: f64 = 4.;
+
+/// width of the outline around the set, in pixels of the screen
+const OUTLINE_PIXELS: f64 = 1.;
+
 fn process_events(app: &mut sfml::graphics::RenderWindow, config: &mut Config) {
     while let Some(event) = app.poll_event() {
         match event {
@@ -109,21 +687,23 @@ fn process_events(app: &mut sfml::graphics::RenderWindow, config: &mut Config) {
             Event::MouseButtonPressed{button, x, y} => {
                 match button {
                     mouse::Button::Left => {
-                        let offset = pos_to_cplx(x, y, &config);
-                        config.zoom *= 2.;
-                        let offset = offset - pos_to_cplx(x, y, config);
-                        let offset = offset + pos_to_cplx((config.size.0/2) as i32, (config.size.1/2) as i32, config);
-                        config.offset = offset;
+                        if config.zoom.mul_pow2(1) > MAX_ZOOM {
+                            continue;
+                        }
+                        let offset = pos_to_delta(x, y, config);
+                        config.zoom = config.zoom.mul_pow2(1);
+                        let offset = offset - pos_to_delta(x, y, config);
+                        move_offset(config, offset);
                     }
                     mouse::Button::Right => {
-                        let offset = pos_to_cplx(x, y, &config);
-                        config.zoom /= 2.;
-                        let offset = offset - pos_to_cplx(x, y, config);
-                        let offset = offset + pos_to_cplx((config.size.0/2) as i32, (config.size.1/2) as i32, config);
-                        config.offset = offset;
+                        let offset = pos_to_delta(x, y, config);
+                        config.zoom = config.zoom.mul_pow2(-1);
+                        let offset = offset - pos_to_delta(x, y, config);
+                        move_offset(config, offset);
                     }
                     mouse::Button::Middle => {
-                        config.offset = pos_to_cplx(x, y, config);
+                        let offset = pos_to_delta(x, y, config);
+                        move_offset(config, offset);
                     }
                     _ => ()
                 }
@@ -154,13 +734,101 @@ fn process_events(app: &mut sfml::graphics::RenderWindow, config: &mut Config) {
                     // }
                     Key::Num0 => {
                         config.redraw = true;
-                        config.zoom = 0.25;
-                        config.offset = cplx::Cplx{re:-0.5,im:0.};
+                        config.zoom = FloatExp::from(0.25);
+                        config.offset = cplx::Cplx{re:BigFloat::from(-0.5),im:BigFloat::from(0.)};
                         config.iter_max = 256;
                     }
                     Key::F3 => {
                         config.debug = !config.debug;
                     }
+                    Key::P => {
+                        config.coloring.palette += 1;
+                        config.recolor = true;
+                    }
+                    Key::M => {
+                        config.coloring.mode = config.coloring.mode.next();
+                        config.recolor = true;
+                    }
+                    Key::I => {
+                        // filled rectangles only have their border computed while the interior is black
+                        if config.coloring.interior == InteriorMode::Black {
+                            config.redraw = true;
+                        }
+                        config.coloring.interior = config.coloring.interior.next();
+                        config.recolor = true;
+                    }
+                    Key::A => {
+                        config.coloring.algorithm = config.coloring.algorithm.next();
+                        // results without sums are computed again, going back to smooth keeps them
+                        let averages = config.coloring.algorithm != Algorithm::Smooth;
+                        if averages && !config.averages {
+                            config.redraw = true;
+                        }
+                        config.averages = averages;
+                        config.recolor = true;
+                    }
+                    Key::F => {
+                        config.coloring.transfer = (config.coloring.transfer + 1) % TRANSFERS.len();
+                        config.recolor = true;
+                    }
+                    Key::Up | Key::Down | Key::Comma | Key::Period | Key::Semicolon | Key::Quote => {
+                        let transfer = &mut config.coloring.transfers[config.coloring.transfer];
+                        match code {
+                            Key::Up => transfer.density *= DENSITY_STEP,
+                            Key::Down => transfer.density /= DENSITY_STEP,
+                            Key::Comma => transfer.phase -= PHASE_STEP,
+                            Key::Period => transfer.phase += PHASE_STEP,
+                            _ => if let Function::Power(exponent) = &mut transfer.function {
+                                *exponent += if code == Key::Quote {EXPONENT_STEP} else {-EXPONENT_STEP};
+                            },
+                        }
+                        config.recolor = true;
+                    }
+                    Key::T => {
+                        let next = match config.trap {
+                            None => 0,
+                            Some(trap) => TRAPS.iter().position(|t| t.shape == trap.shape).unwrap() + 1,
+                        };
+                        config.trap = TRAPS.get(next).copied();
+                        config.redraw = true;
+                    }
+                    Key::C => {
+                        let mouse_pos = mouse::desktop_position() - app.position();
+                        let center = pos_to_cplx(mouse_pos.x, mouse_pos.y, config);
+                        if let Some(trap) = &mut config.trap {
+                            trap.center = center;
+                            config.redraw = true;
+                        }
+                    }
+                    Key::LBracket | Key::RBracket => {
+                        if let Some(trap) = &mut config.trap {
+                            trap.size *= if code == Key::LBracket {0.5} else {2.};
+                            config.redraw = true;
+                        }
+                    }
+                    Key::B => {
+                        config.coloring.trap_blend = (config.coloring.trap_blend + 0.25) % 1.25;
+                        config.recolor = true;
+                    }
+                    Key::S => {
+                        config.export = true;
+                    }
+                    Key::H => {
+                        config.coloring.equalize = !config.coloring.equalize;
+                        config.recolor = true;
+                    }
+                    Key::O => {
+                        config.coloring.outline = !config.coloring.outline;
+                        config.recolor = true;
+                    }
+                    Key::Left => {
+                        config.coloring.light -= PI/12.;
+                        config.recolor = true;
+                    }
+                    Key::Right => {
+                        config.coloring.light += PI/12.;
+                        config.recolor = true;
+                    }
                     _ => ()
                 }
             }
@@ -170,12 +838,51 @@ fn process_events(app: &mut sfml::graphics::RenderWindow, config: &mut Config) {
 }
 
 #[inline]
-fn get_color(m: &Mandel) -> Color {
+/// `pixel` is the width of a pixel of the screen in the plane, `histogram` the one of the
+/// frame when it's equalized
+fn get_color(m: &Mandel, coloring: &Coloring, palette: &Palette, pixel: FloatExp, histogram: Option<&Histogram>) -> Color {
+    let color = iteration_color(m, coloring, palette, pixel, histogram);
+    match m.get_trap() {
+        Some((distance, iteration)) if coloring.trap_blend > 0. => {
+            // hue by when the orbit came closest, brightness by how close
+            let v = (255. / (1. + distance)) as u8;
+            let trap = palette.color(0.5*(iteration as f64).sqrt()) * Color::rgb(v, v, v);
+            colors::mix(color, trap, coloring.trap_blend)
+        }
+        _ => color,
+    }
+}
+
+/// what the palette is indexed with for a point that escaped after `n` iterations
+fn palette_index(m: &Mandel, n: f64, coloring: &Coloring) -> f64 {
+    // let n = 0.5*mandel::fast_log2(n);
+    match (coloring.algorithm, m.get_averages()) {
+        (Algorithm::Triangle, Some(averages)) => averages.triangle(),
+        (Algorithm::Stripe, Some(averages)) => averages.stripe(),
+        (Algorithm::Curvature, Some(averages)) => averages.curvature(),
+        // also while the results with averages come in
+        _ => coloring.transfers[coloring.transfer].apply(n),
+    }
+}
+
+/// distribution of the palette indices of the pixels that escaped
+fn equalization(mandels: &[Mandel], coloring: &Coloring) -> Histogram {
+    Histogram::new(mandels.iter().filter_map(|m| {
+        let n = m.get_finished().filter(|n| n.is_finite())?;
+        Some(palette_index(m, n, coloring))
+    }))
+}
+
+/// color from the smooth iteration count or the interior, without the trap
+fn iteration_color(m: &Mandel, coloring: &Coloring, palette: &Palette, pixel: FloatExp, histogram: Option<&Histogram>) -> Color {
     match m.get_finished() {
         Some(n) => {
             if n.is_finite() {
-                // let n = 0.5*mandel::fast_log2(n);
-                let n = 0.5*n.sqrt() + 3.3;
+                let n = palette_index(m, n, coloring);
+                let n = match histogram {
+                    Some(histogram) => EQUALIZED_CYCLES*histogram.rank(n),
+                    None => n,
+                };
                 // let n = n/8.;
                 // let p = n.fract();
                 // let p2 = n2.fract();
@@ -187,61 +894,136 @@ fn get_color(m: &Mandel) -> Color {
                 // let shadow = 128 + ((1.-p2)*128.) as u8;
                 // let shadow2 = 192 + ((1.-p)*64.) as u8;
                 // let shadow = (n*256.).abs() as u8;
-                const LIGHT:(f64, f64, f64) = (-SQRT_2, -SQRT_2, 1.);
+                // 2 sideways for 1 up
+                let light = (2.*coloring.light.cos(), 2.*coloring.light.sin(), 1.);
                 let normal = m.get_shadow().unwrap();
-                let shadow = (LIGHT.0*normal.re + LIGHT.1*normal.im + LIGHT.2) / (1.+LIGHT.2);
+                let shadow = (light.0*normal.re + light.1*normal.im + light.2) / (1.+light.2);
                 let shadow = colors::hsv_to_rgb(185., 0., 0.5+0.5*shadow);
                 // let shadow = colors::hsv_to_rgb(185., 0.1*(1.-shadow), 0.75+0.25*shadow);
                 // let color = colors::hsv_to_rgb(n*32., 0.8, 0.8);
                 // let color = colors::hsv_to_rgb(15.*n, 0.7, 0.8-p*0.5);
-                let color = colors::mm_color(n);
+                let color = palette.color(n);
                 // let color = Color::WHITE;
-                let color = color * shadow;
                 // let color = color * Color::rgb(shadow, shadow, shadow);
                 // let color = color * Color::rgb(shadow2, shadow2, shadow2);
-                color
+                let distance = (m.get_distance().unwrap() / pixel).to_f64();
+                let color = match coloring.mode {
+                    ColorMode::Shaded => color * shadow,
+                    ColorMode::Palette => color,
+                    ColorMode::Relief => shadow,
+                    // one palette step every doubling
+                    ColorMode::Distance => palette.color(0.5*distance.log2()),
+                };
+                outline(color, distance, coloring)
             } else {
-                Color::BLACK
+                interior_color(m, coloring, palette, pixel)
             }
         },
         None => Color::BLACK
     }
 }
 
-fn generate_bg(pic: Image, old: Config, config: &Config) -> Image {
-    // pic = Image::new((config.size.0*config.AA) as u32, (config.size.1*config.AA) as u32);
+/// color of a point that didn't escape, black when no cycle was found
+fn interior_color(m: &Mandel, coloring: &Coloring, palette: &Palette, pixel: FloatExp) -> Color {
+    let (Some(period), Some(multiplier), Some(distance)) = (m.get_period(), m.get_multiplier(), m.get_interior_distance()) else {
+        return Color::BLACK;
+    };
+    let distance = distance / pixel.to_f64();
+    let color = match coloring.interior {
+        InteriorMode::Black => Color::BLACK,
+        InteriorMode::Magnitude => palette.color(multiplier.abs()),
+        InteriorMode::Angle => {
+            let angle = multiplier.im.atan2(multiplier.re).to_degrees().rem_euclid(360.);
+            colors::hsv_to_rgb(angle, 0.6, 0.3 + 0.5*multiplier.abs())
+        }
+        InteriorMode::Distance => palette.color(0.5*distance.log2()),
+        // golden angle, neighbouring periods get far apart hues
+        InteriorMode::Period => colors::hsv_to_rgb((period as f64*137.5).rem_euclid(360.), 0.6, 0.7),
+    };
+    outline(color, distance, coloring)
+}
 
-    let scale = config.zoom/old.zoom;
-    let mut texture = Texture::new().unwrap();
-    texture.load_from_image(&pic, Rect {left: 0, top: 0, width: (config.size.0*config.aa) as i32, height: (config.size.1*config.aa) as i32}).expect("msg");
-    texture.set_smooth(true);
-    let mut sprite = Sprite::with_texture(&texture);
-    sprite.set_origin(((config.size.0*config.aa) as f32/2., (config.size.1*config.aa) as f32/2.));
-    // sprite.scale((1./config.AA as f32, 1./config.AA as f32));
+/// `color` darkened when it's within the outline, `distance` is in pixels
+fn outline(color: Color, distance: f64, coloring: &Coloring) -> Color {
+    if coloring.outline && distance < OUTLINE_PIXELS {
+        let v = (255. * distance / OUTLINE_PIXELS) as u8;
+        color * Color::rgb(v, v, v)
+    } else {
+        color
+    }
+}
+
+/// colors every pixel again with the current coloring, pixels without a result of their own
+/// take the one of the coarse sample they were drawn from
+fn recolor(pic: &mut Image, mandels: &[Mandel], coloring: &Coloring, palette: &Palette, pixel: FloatExp, histogram: Option<&Histogram>) {
+    let width = pic.size().x as usize;
+    for i in 0..mandels.len() {
+        let (x, y) = (i%width, i/width);
+        let sample = std::iter::once(1).chain(PASSES.iter().rev().copied())
+            .map(|step| &mandels[(y - y%step)*width + x - x%step])
+            .find(|m| m.get_finished().is_some());
+        if let Some(m) = sample {
+            unsafe {
+                pic.set_pixel(x as u32, y as u32, get_color(m, coloring, palette, pixel, histogram));
+            }
+        }
+    }
+}
 
-    sprite.set_position((cplx_to_pos(old.offset, &config).x*config.aa as f32, cplx_to_pos(old.offset, &config).y*config.aa as f32));
-    sprite.scale((scale as f32, scale as f32));
+/// old pixel of every new column or row, where the pixel grids of the two views line up
+type PixelMap = Vec<Option<usize>>;
 
-    let mut bg = RenderTexture::new((config.size.0*config.aa) as u32, (config.size.1*config.aa) as u32).expect("rendertext");
-    bg.draw(&sprite);
-    let mut red = RectangleShape::new();
-    red.set_fill_color(Color { r: 255, g: 0, b: 0, a: 64 });
-    red.set_size(((config.size.0*config.aa) as f32, (config.size.1*config.aa) as f32));
-    bg.draw(&red);
-    bg.display();
-    bg.texture().copy_to_image().expect("text-to-pic")
+/// maps of the columns and rows, `None` when nothing can be carried over: other size, iteration
+/// count or number type
+fn pixel_map(old: &Config, old_precision: Precision, new: &Config, precision: Precision) -> Option<(PixelMap, PixelMap)> {
+    // results with averages can go where they're not needed
+    if old.size != new.size || old.iter_max != new.iter_max || old.trap != new.trap || (new.averages && !old.averages) || old_precision != precision {
+        return None;
+    }
+    let min = std::cmp::min(new.size.0, new.size.1) as f64;
+    // new pixel x is old pixel half + scale*(x - half) + shift
+    let scale = (old.zoom/new.zoom).to_f64();
+    let shift = (new.offset - old.offset).to_floatexp();
+    let axis = |size: usize, shift: FloatExp| {
+        let half = (size/2) as f64;
+        let shift = (shift*old.zoom).to_f64()*min;
+        (0..size).map(|x| {
+            let old = half + scale*(x as f64 - half) + shift;
+            let rounded = old.round();
+            if (old - rounded).abs() < 1e-3 && rounded >= 0. && rounded < size as f64 {Some(rounded as usize)} else {None}
+        }).collect()
+    };
+    Some((axis(new.size.0, shift.re), axis(new.size.1, shift.im)))
 }
 
+/// copies `rect` of `pic` to the same place in `texture`, they have the same size
+fn upload(texture: &mut Texture, pic: &Image, rect: Rect<usize>) {
+    let width = pic.size().x as usize;
+    let data = pic.pixel_data();
+    let mut rgba = Vec::with_capacity(rect.width*rect.height*4);
+    for y in rect.top..rect.top+rect.height {
+        rgba.extend_from_slice(&data[(y*width + rect.left)*4..(y*width + rect.left + rect.width)*4]);
+    }
+    unsafe {
+        texture.update_from_pixels(&rgba, rect.width as u32, rect.height as u32, rect.left as u32, rect.top as u32);
+    }
+}
 
 fn main() {
     let mut config: Config = Config{
         size: (640, 480),
-        zoom: 0.25,
-        offset: cplx::Cplx{re:-0.5,im:0.},
+        zoom: FloatExp::from(0.25),
+        offset: cplx::Cplx{re:BigFloat::from(-0.5),im:BigFloat::from(0.)},
         iter_max: 256,
         redraw: true,
         debug: true,
         aa: 2,
+        threads: 0,
+        trap: None,
+        averages: false,
+        coloring: Coloring {mode: ColorMode::Shaded, algorithm: Algorithm::Smooth, transfers: TRANSFERS, transfer: 0, palette: 0, interior: InteriorMode::Black, light: -0.75*PI, outline: false, trap_blend: 0., equalize: false},
+        recolor: false,
+        export: false,
     };
 
     let mut settings = sfml::window::ContextSettings::default();
@@ -255,42 +1037,147 @@ fn main() {
     );
     app.set_position(sfml::system::Vector2i::new(0, 0));
 
-    // let mut mandels = vec![vec![Mandel::new_empty();config.size.0*2];config.size.1*2];
 
     let mut pic = Image::new((config.size.0*config.aa) as u32, (config.size.1*config.aa) as u32);
+    // kept on the gpu, only the tiles that come in are uploaded
+    // results of the last render, pixels not computed yet have no value
+    let mut mandels = vec![Mandel::new_empty(); config.size.0*config.aa*config.size.1*config.aa];
+    let mut last_render = None;
+    let mut texture = Texture::new().unwrap();
+    texture.load_from_image(&pic, Rect {left: 0, top: 0, width: (config.size.0*config.aa) as i32, height: (config.size.1*config.aa) as i32}).expect("msg");
+    texture.set_smooth(true);
 
     let fira = sfml::graphics::Font::from_file("fira.otf").unwrap();
 
-    let mut tx_calc;
+    let pool = Pool::new(config.threads);
+    let generations = Generations::default();
+    let mut token = generations.next();
     let (_, mut rx_calc) = mpsc::channel();
+    // set by the first job of the render once the orbit is built
+    let mut reference: Arc<OnceLock<Arc<Reference>>> = Arc::default();
+    let mut precision = Precision::F64;
+    let mut stats = RenderStats::default();
+    let mut last_stats = None;
+    // palette last exported and its file, shown in the overlay while it's the current one
+    let mut exported = None;
+    // gradient files given on the command line come after the presets
+    let mut palettes = palette::presets();
+    for path in std::env::args().skip(1) {
+        match file::load(std::path::Path::new(&path)) {
+            Ok(palette) => palettes.push(palette),
+            Err(e) => eprintln!("{path}: {e}"),
+        }
+    }
+    // all the results of the current view are in
+    let mut finished = false;
+    // of the last finished frame, the next one is colored with it until it's done
+    let mut histogram = None;
 
     while app.is_open() {
         let frame_start = Instant::now();
-        let old = config;
         process_events(&mut app, &mut config);
-        let debug_txt;
+        config.coloring.palette %= palettes.len();
+        if config.export {
+            config.export = false;
+            let palette = &palettes[config.coloring.palette];
+            let stem = palette.name.replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
+            let path = format!("{stem}.{}", EXPORT_FORMAT.extension());
+            match file::save(std::path::Path::new(&path), palette) {
+                Ok(()) => exported = Some((config.coloring.palette, path)),
+                Err(e) => eprintln!("{path}: {e}"),
+            }
+        }
+        let mut debug_txt;
+        // of the screen, before the size is multiplied by the antialiasing
+        let pixel = pixel_size(&config);
+
+        // a redraw colors everything anyway
+        if config.recolor && !config.redraw {
+            histogram = config.coloring.equalize.then(|| equalization(&mandels, &config.coloring));
+            recolor(&mut pic, &mandels, &config.coloring, &palettes[config.coloring.palette], pixel, histogram.as_ref());
+            let size = pic.size();
+            texture.load_from_image(&pic, Rect {left: 0, top: 0, width: size.x as i32, height: size.y as i32}).expect("msg");
+        }
+        config.recolor = false;
 
         if config.redraw {
             config.redraw = false;
+            finished = false;
+            if !config.coloring.equalize {
+                histogram = None;
+            }
 
+            // cancels the jobs of the previous render
+            token = generations.next();
+            let tx_calc;
             (tx_calc, rx_calc) = mpsc::channel();
+            stats = RenderStats::default();
             config.size.0 *= config.aa;
             config.size.1 *= config.aa;
-            // mandels = vec![vec![Mandel::new_empty();config.size.0];config.size.1];
-            area(tx_calc.clone(), Rect{left:0, top:0, width:config.size.0, height:config.size.1}, config);
+            // picked at the rendered size, antialiasing makes pixels smaller
+            precision = Precision::pick(&config);
+            reference = Arc::default();
+
+            // pixels that land exactly on a pixel of the previous view keep their result
+            let known = Arc::new(Known::new(config.size));
+            let mut carried = vec![Mandel::new_empty(); config.size.0*config.size.1];
+            pic = Image::new(config.size.0 as u32, config.size.1 as u32);
+            if let Some((xs, ys)) = last_render.and_then(|(old, old_precision)| pixel_map(&old, old_precision, &config, precision)) {
+                for (y, oy) in ys.iter().enumerate() {
+                    let Some(oy) = oy else {continue};
+                    for (x, ox) in xs.iter().enumerate() {
+                        let Some(ox) = ox else {continue};
+                        let m = mandels[oy*config.size.0 + ox];
+                        // filled pixels have no interior color to give
+                        let filled = m.get_filled() && config.coloring.interior != InteriorMode::Black;
+                        if m.get_finished().is_some() && !filled {
+                            carried[y*config.size.0 + x] = m;
+                            known.set(x, y, &m);
+                            unsafe {
+                                pic.set_pixel(x as u32, y as u32, get_color(&m, &config.coloring, &palettes[config.coloring.palette], pixel, histogram.as_ref()));
+                            }
+                        }
+                    }
+                }
+            }
+            mandels = carried;
+            texture.load_from_image(&pic, Rect {left: 0, top: 0, width: config.size.0 as i32, height: config.size.1 as i32}).expect("msg");
+            last_render = Some((config, precision));
+
+            // no sender is kept here, the channel disconnects once every job is done
+            let mut render = Render {tx: tx_calc, config, precision, reference: None, known, token: token.clone()};
+            let built = reference.clone();
+            // the reference orbit first, it can take a while deep in, then the coarse passes fill
+            // in what wasn't carried over
+            pool.spawn(move |worker| {
+                render.reference = render.precision.reference(&render.config, &render.token).map(Arc::new);
+                if render.cancelled() {
+                    return;
+                }
+                if let Some(reference) = &render.reference {
+                    let _ = built.set(reference.clone());
+                }
+                pass(render, 0, worker);
+            });
             config.size.0 /= config.aa;
             config.size.1 /= config.aa;
 
-            pic = generate_bg(pic, old, &config);
         }
 
         let mut orbit: Vec<sfml::graphics::Vertex> = Vec::new();
         //calculate orbit to show
         {
             let mouse_pos = sfml::window::mouse::desktop_position() - app.position();
-            let pos = pos_to_cplx(mouse_pos.x, mouse_pos.y, &config);
+            let pos = pos_to_cplx::<f64>(mouse_pos.x, mouse_pos.y, &config);
             let mut z = pos;
             debug_txt = format!("mouse pos: [{}, {}]", pos.re, pos.im);
+            let (x, y) = (mouse_pos.x as usize * config.aa, mouse_pos.y as usize * config.aa);
+            let width = pic.size().x as usize;
+            if mouse_pos.x >= 0 && mouse_pos.y >= 0 && x < width && y < pic.size().y as usize {
+                if let Some(period) = mandels[y*width + x].get_period() {
+                    debug_txt += &format!("\nperiod: {period}");
+                }
+            }
             const M: f64 = 32.;
             for _ in 1..config.iter_max {
                 orbit.push(sfml::graphics::Vertex{position: cplx_to_pos(z, &config), color: sfml::graphics::Color::RED,tex_coords: Vector2f{x: 0.,y: 0.}});
@@ -303,13 +1190,50 @@ fn main() {
 
         loop {
             match rx_calc.try_recv() {
-                Ok((x, y, m)) => {
-                    // mandels[x][y] = m;
-                    unsafe {
-                        pic.set_pixel(x as u32, y as u32, get_color(&m));
+                // a stale result would draw the previous view over this one
+                Ok(tile) if tile.generation != token.generation() => (),
+                Ok(Tile {rect, step, pixels, ..}) => {
+                    let width = pic.size().x as usize;
+                    let columns = rect.width.div_ceil(step);
+                    for (i, m) in pixels.into_iter().enumerate() {
+                        let (x, y) = (rect.left + i%columns*step, rect.top + i/columns*step);
+                        if let Some(m) = m {
+                            stats.add(&m);
+                            mandels[y*width + x] = m;
+                        }
+                        // known samples are drawn again at the size of this pass
+                        let m = &mandels[y*width + x];
+                        if m.get_finished().is_none() {
+                            continue;
+                        }
+                        let color = get_color(m, &config.coloring, &palettes[config.coloring.palette], pixel, histogram.as_ref());
+                        for py in y..(y+step).min(rect.top+rect.height) {
+                            for px in x..(x+step).min(rect.left+rect.width) {
+                                // pixels with their own result keep it
+                                if (px, py) != (x, y) && mandels[py*width + px].get_finished().is_some() {
+                                    continue;
+                                }
+                                unsafe {
+                                    pic.set_pixel(px as u32, py as u32, color);
+                                }
+                            }
+                        }
                     }
+                    upload(&mut texture, &pic, rect);
                 },
-                Err(_) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    last_stats = Some(stats);
+                    // second pass, the distribution needs the whole frame
+                    if !finished && config.coloring.equalize {
+                        histogram = Some(equalization(&mandels, &config.coloring));
+                        recolor(&mut pic, &mandels, &config.coloring, &palettes[config.coloring.palette], pixel, histogram.as_ref());
+                        let size = pic.size();
+                        upload(&mut texture, &pic, Rect{left: 0, top: 0, width: size.x as usize, height: size.y as usize});
+                    }
+                    finished = true;
+                    break;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
             }
             if frame_start.elapsed() >= Duration::from_secs_f64(1./40.) {break;}
         }
@@ -317,9 +1241,6 @@ fn main() {
 
         app.clear(Color::BLACK);
 
-        let mut texture = Texture::new().unwrap();
-        texture.load_from_image(&pic, Rect {left: 0, top: 0, width: (config.size.0*config.aa) as i32, height: (config.size.1*config.aa) as i32}).expect("msg");
-        texture.set_smooth(true);
         let mut sprite = Sprite::with_texture(&texture);
         sprite.scale((1./config.aa as f32, 1./config.aa as f32));
         app.draw(&sprite);
@@ -327,7 +1248,48 @@ fn main() {
         app.draw_primitives(&orbit, sfml::graphics::PrimitiveType::LINE_STRIP, &sfml::graphics::RenderStates::DEFAULT);
 
         if config.debug {
-            let txt = format!("pos: {} + {}i\nzoom: 2^{}\niter max: {}\n{debug_txt}", config.offset.re, config.offset.im, config.zoom.log2(), config.iter_max);
+            let mut txt = format!("pos: {} + {}i\nzoom: 2^{}", config.offset.re, config.offset.im, config.zoom.log2());
+            if config.zoom.mul_pow2(1) > MAX_ZOOM {
+                txt += &format!(" (deepest, BigFloat holds {} bits)", MAX_LIMBS*64);
+            }
+            txt += &format!("\niter max: {}\n{debug_txt}", config.iter_max);
+            txt += &format!("\ncoloring: {} {}, palette {}, light {:.0}°", config.coloring.algorithm, config.coloring.mode, palettes[config.coloring.palette].name, config.coloring.light.to_degrees().rem_euclid(360.));
+            if let Some((_, path)) = exported.as_ref().filter(|(palette, _)| *palette == config.coloring.palette) {
+                txt += &format!(", written to {path}");
+            }
+            if config.coloring.outline {
+                txt += ", outline";
+            }
+            if config.coloring.equalize {
+                txt += ", equalized";
+            }
+            let transfer = config.coloring.transfers[config.coloring.transfer];
+            txt += &format!("\ntransfer: {} x{:.3} {:+.2}", transfer.function, transfer.density, transfer.phase);
+            txt += &format!(", interior {}", config.coloring.interior);
+            if let Some(trap) = config.trap {
+                txt += &format!("\ntrap: {} at {} + {}i of size {}, blend {:.0}%", trap.shape, trap.center.re, trap.center.im, trap.size, 100.*config.coloring.trap_blend);
+            }
+            txt += &format!("\nthreads: {}", pool.threads());
+            txt += &format!("\nprecision: {precision}");
+            if matches!(precision, Precision::F32 | Precision::F64) {
+                txt += &format!(" ({})", simd::Kernel::detect());
+            }
+            if precision == Precision::BigFloat {
+                txt += &format!(" ({} bits)", config.offset.re.prec());
+            }
+            match reference.get() {
+                Some(reference) => txt += &format!("\nreference orbit: {} iterations", reference.iterations()),
+                None if !matches!(precision, Precision::F32 | Precision::F64) => txt += "\nreference orbit: computing",
+                None => (),
+            }
+            if let Some(stats) = last_stats {
+                txt += &format!("\nlast frame: {} pixels, {} glitched, {} corrected", stats.pixels, stats.glitched, stats.corrected);
+                txt += &format!("\nskipped: {} iterations ({:.1} per pixel)", stats.skipped, stats.skipped as f64 / stats.pixels.max(1) as f64);
+                txt += &format!("\nperiodic: {} pixels", stats.periodic);
+                if !matches!(precision, Precision::F32 | Precision::F64) {
+                    txt += " (no period with a reference orbit)";
+                }
+            }
 
             let mut text = sfml::graphics::Text::new(&txt, &fira, 24);
             text.set_outline_thickness(2.);
@@ -339,3 +1301,83 @@ fn main() {
 
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `size` pixels around `center`, zoomed in until a pixel is as narrow as f32 is allowed
+    fn threshold_view(center: Cplx<f64>, size: (usize, usize), iter_max: usize) -> Config {
+        // pixel = ulps*eps*(|center| + half the view), solved for the pixel
+        let k = F32_PIXEL_ULPS * f32::EPSILON as f64;
+        let half = (size.0.max(size.1)/2) as f64;
+        let pixel = k*center.re.abs().max(center.im.abs()) / (1. - k*half);
+        let min = std::cmp::min(size.0, size.1) as f64;
+        Config {
+            size,
+            // a hair wider so rounding stays on the f32 side
+            zoom: FloatExp::from(1. / (pixel*min*(1. + 1e-9))),
+            offset: Cplx{re: BigFloat::from(center.re), im: BigFloat::from(center.im)},
+            iter_max,
+            redraw: false,
+            debug: false,
+            aa: 1,
+            threads: 1,
+            trap: None,
+            averages: false,
+            coloring: Coloring {mode: ColorMode::Shaded, algorithm: Algorithm::Smooth, transfers: TRANSFERS, transfer: 0, palette: 0, interior: InteriorMode::Black, light: 0., outline: false, trap_blend: 0., equalize: false},
+            recolor: false,
+            export: false,
+        }
+    }
+
+    /// the bounds given with `F32_PIXEL_ULPS`, at 256 iterations
+    #[test]
+    fn f32_matches_f64_at_threshold() {
+        let (width, height) = (160, 160);
+        // pixels, flipped, escaped, off by half an iteration, smooth, off by a quarter step there
+        let (mut pixels, mut flipped, mut escaped, mut off, mut smooth, mut off_smooth) = (0, 0, 0, 0, 0, 0);
+        let centers = [(-0.7436, 0.1318), (-1.25, 0.02), (0.28, 0.53), (-0.1, 0.9), (-1.768, 0.001), (0.36, 0.1), (-0.16, 1.035)];
+        for (re, im) in centers {
+            let config = threshold_view(Cplx{re, im}, (width, height), 256);
+            assert!(Precision::pick(&config) == Precision::F32);
+            let pixel = pixel_size(&config).to_f64();
+            let n: Vec<_> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| {
+                let mut m32 = Mandel::<f32>::new(pos_to_cplx(x as i32, y as i32, &config), config.iter_max, pixel, None, false);
+                m32.calculate_mandel_smooth();
+                let mut m64 = Mandel::<f64>::new(pos_to_cplx(x as i32, y as i32, &config), config.iter_max, pixel, None, false);
+                m64.calculate_mandel_smooth();
+                (m32.get_finished().unwrap(), m64.get_finished().unwrap())
+            }).collect();
+            for y in 1..height-1 {
+                for x in 1..width-1 {
+                    let (n32, n64) = n[y*width + x];
+                    pixels += 1;
+                    if n32.is_finite() != n64.is_finite() {
+                        flipped += 1;
+                    }
+                    if !n32.is_finite() || !n64.is_finite() {
+                        continue;
+                    }
+                    escaped += 1;
+                    if (n32 - n64).abs() > 0.5 {
+                        off += 1;
+                    }
+                    // biggest change to a neighbour, infinite when one of them is inside
+                    let step = [(x-1, y), (x+1, y), (x, y-1), (x, y+1)].iter()
+                        .map(|&(x, y)| (n[y*width + x].1 - n64).abs())
+                        .fold(0., f64::max);
+                    if step <= 1. {
+                        smooth += 1;
+                        if (n32 - n64).abs() > 0.25*step {
+                            off_smooth += 1;
+                        }
+                    }
+                }
+            }
+        }
+        assert!(off_smooth == 0, "{off_smooth} of {smooth} smooth pixels off by more than a quarter step");
+        assert!(flipped*500 < pixels, "{flipped} of {pixels} pixels flipped");
+        assert!(off*50 < escaped, "{off} of {escaped} escaped pixels off by more than half an iteration");
+    }
+}
@@ -0,0 +1,110 @@
+use std::f64::consts::PI;
+
+use super::cplx::Cplx;
+use super::float::Float;
+use super::{Mandel, M};
+
+/// turns of the stripes around the origin
+const STRIPE_DENSITY: f64 = 5.;
+
+/// running sum of one average coloring, the last term is kept for the interpolation
+#[derive(Clone, Copy, Default)]
+struct Sum {
+    total: f64,
+    last: f64,
+    count: usize,
+}
+
+impl Sum {
+    #[inline]
+    fn add(&mut self, x: f64) {
+        // the triangle term is 0/0 when z_(i-1)² and c line up exactly
+        if x.is_finite() {
+            self.total += x;
+            self.last = x;
+            self.count += 1;
+        }
+    }
+
+    /// between the average without and with the last term
+    fn average(&self, d: f64) -> f64 {
+        if self.count < 2 {
+            return self.total;
+        }
+        let with = self.total / self.count as f64;
+        let without = (self.total - self.last) / (self.count - 1) as f64;
+        without + (with - without)*d
+    }
+}
+
+/// triangle inequality, stripe and curvature averages of the orbit
+///
+/// every iteration adds a value in [0, 1] to each, at escape the average is interpolated between
+/// the one without the last iteration and the one with it by how far past the escape radius `z`
+/// went, the same fraction the smooth iteration count uses, so there are no bands
+#[derive(Clone, Copy, Default)]
+pub struct Averages {
+    /// z_(i-1) and z_(i-2)
+    previous: [Cplx<f64>; 2],
+    iterations: usize,
+    triangle: Sum,
+    stripe: Sum,
+    curvature: Sum,
+    /// 1 right at the escape radius down to 0 at its square
+    d: f64,
+}
+
+impl Averages {
+    /// adds `z`, the i-th point of the orbit
+    #[inline]
+    fn add(&mut self, z: Cplx<f64>, c: Cplx<f64>) {
+        let [z1, z2] = self.previous;
+        self.iterations += 1;
+        self.stripe.add(0.5*(STRIPE_DENSITY*z.im.atan2(z.re)).sin() + 0.5);
+        if self.iterations >= 2 {
+            // where |z| lands between the bounds of |z_(i-1)² + c|
+            let m = z1.sq_abs();
+            let low = (m - c.abs()).abs();
+            let high = m + c.abs();
+            self.triangle.add((z.abs() - low) / (high - low));
+        }
+        if self.iterations >= 3 {
+            // how sharply the orbit turns
+            let turn = (z - z1)/(z1 - z2);
+            self.curvature.add(turn.im.atan2(turn.re).abs() / PI);
+        }
+        self.previous = [z, z1];
+    }
+
+    /// `z` is the first point past the escape radius
+    fn finish(&mut self, z: Cplx<f64>) {
+        self.d = 1. + (M.ln() / z.abs().ln()).log2();
+    }
+
+    pub fn triangle(&self) -> f64 {
+        self.triangle.average(self.d)
+    }
+
+    pub fn stripe(&self) -> f64 {
+        self.stripe.average(self.d)
+    }
+
+    pub fn curvature(&self) -> f64 {
+        self.curvature.average(self.d)
+    }
+}
+
+impl<T: Float> Mandel<T> {
+    #[inline]
+    pub(super) fn add_averages<D: Float>(&mut self, z: &Cplx<D>) {
+        if let Some(averages) = &mut self.averages {
+            averages.add(z.to_f64(), self.c.to_f64());
+        }
+    }
+
+    pub(super) fn finish_averages(&mut self, z: Cplx<f64>) {
+        if let Some(averages) = &mut self.averages {
+            averages.finish(z);
+        }
+    }
+}
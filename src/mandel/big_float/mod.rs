@@ -1,4 +1,664 @@
-// struct BigFloat {
-//     e: i64,
-//     m: num::BigInt
-// }
\ No newline at end of file
+use std::cmp::Ordering;
+use std::f64::consts::LOG10_2;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// max number of 64 bits limbs a value can hold (2048 bits), zoom is capped at 2^1984, past that
+/// the limbs can't place a pixel
+pub const MAX_LIMBS: usize = 32;
+
+/// arbitrary precision float, value is `±0.m * 2^e`
+///
+/// `m` is normalized so the top bit of `m[0]` is set, only the first `prec` limbs are used.
+/// the array is fixed size so the type stays `Copy` and works with the generic ops of `Cplx`.
+/// results of an operation get the precision of the most precise operand.
+#[derive(Clone, Copy)]
+pub struct BigFloat {
+    neg: bool,
+    e: i64,
+    prec: usize,
+    m: [u64; MAX_LIMBS],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBigFloatError(String);
+
+impl fmt::Display for ParseBigFloatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid float literal: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseBigFloatError {}
+
+#[inline]
+fn limbs(bits: usize) -> usize {
+    bits.div_ceil(64).clamp(1, MAX_LIMBS)
+}
+
+/// x * 2^exp, without overflowing the intermediate power
+fn ldexp(mut x: f64, mut exp: i64) -> f64 {
+    while exp > 1000 {
+        x *= 2f64.powi(1000);
+        exp -= 1000;
+        if x.is_infinite() {return x;}
+    }
+    while exp < -1000 {
+        x *= 2f64.powi(-1000);
+        exp += 1000;
+        if x == 0. {return x;}
+    }
+    x * 2f64.powi(exp as i32)
+}
+
+/// dst = src >> bits, limbs shifted out of dst are dropped
+fn shr_into(src: &[u64], bits: usize, dst: &mut [u64]) {
+    let q = (bits / 64) as isize;
+    let r = bits % 64;
+    let get = |i: isize| if i >= 0 && (i as usize) < src.len() {src[i as usize]} else {0};
+    for (i, d) in dst.iter_mut().enumerate() {
+        let j = i as isize - q;
+        *d = if r == 0 {get(j)} else {get(j) >> r | get(j - 1) << (64 - r)};
+    }
+}
+
+fn shl_in_place(buf: &mut [u64], bits: usize) {
+    let q = bits / 64;
+    let r = bits % 64;
+    let len = buf.len();
+    for i in 0..len {
+        let get = |j: usize| if j < len {buf[j]} else {0};
+        buf[i] = if r == 0 {get(i + q)} else {get(i + q) << r | get(i + q + 1) >> (64 - r)};
+    }
+}
+
+fn cmp_limbs(a: &[u64], b: &[u64]) -> Ordering {
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        match x.cmp(&y) {
+            Ordering::Equal => (),
+            o => return o,
+        }
+    }
+    Ordering::Equal
+}
+
+impl BigFloat {
+    pub fn zero(bits: usize) -> Self {
+        BigFloat { neg: false, e: 0, prec: limbs(bits), m: [0; MAX_LIMBS] }
+    }
+
+    /// NaN and infinities have no representation and become zero
+    pub fn from_f64(x: f64, bits: usize) -> Self {
+        let mut r = Self::zero(bits);
+        if x == 0. || !x.is_finite() {
+            return r;
+        }
+        let b = x.to_bits();
+        let biased = ((b >> 52) & 0x7FF) as i64;
+        let frac = b & ((1 << 52) - 1);
+        let (m, exp) = if biased == 0 {(frac, -1074)} else {(frac | 1 << 52, biased - 1075)};
+        let lz = m.leading_zeros() as i64;
+        r.neg = x < 0.;
+        r.m[0] = m << lz;
+        r.e = exp + 64 - lz;
+        r
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        if self.is_zero() {
+            return 0.;
+        }
+        let x = ldexp(self.m[0] as f64, self.e - 64);
+        if self.neg {-x} else {x}
+    }
+
+    /// precision in bits
+    #[inline]
+    pub fn prec(&self) -> usize {
+        self.prec * 64
+    }
+
+    /// same value rounded (or zero extended) to `bits` of precision
+    pub fn with_prec(self, bits: usize) -> Self {
+        let p = limbs(bits);
+        if p >= self.prec {
+            let mut r = self;
+            r.prec = p;
+            return r;
+        }
+        Self::pack(self.neg, self.e, &self.m[..p + 1], p)
+    }
+
+    #[inline]
+    pub fn is_zero(&self) -> bool {
+        self.m[0] == 0
+    }
+
+    #[inline]
+    pub fn is_sign_negative(&self) -> bool {
+        self.neg && !self.is_zero()
+    }
+
+    pub fn abs(&self) -> Self {
+        let mut r = *self;
+        r.neg = false;
+        r
+    }
+
+    pub fn square(&self) -> Self {
+        *self * *self
+    }
+
+    /// self * 2^k, exact
+    pub fn mul_pow2(&self, k: i64) -> Self {
+        let mut r = *self;
+        if !r.is_zero() {
+            r.e += k;
+        }
+        r
+    }
+
+    /// 1/self, by newton iterations starting from the f64 reciprocal
+    pub fn recip(&self) -> Self {
+        assert!(!self.is_zero(), "BigFloat division by zero");
+        // a = 0.m in [0.5, 1), self = a * 2^e
+        let mut a = *self;
+        a.e = 0;
+        a.neg = false;
+        let one = Self::from_f64(1., self.prec());
+        let mut x = Self::from_f64(1. / a.to_f64(), self.prec());
+        let mut bits = 50;
+        while bits < self.prec() + 64 {
+            x = x + x * (one - a * x);
+            bits *= 2;
+        }
+        x.neg = self.neg;
+        x.mul_pow2(-self.e)
+    }
+
+    /// newton iterations on 1/sqrt starting from the f64 one, negative values give zero
+    pub fn sqrt(&self) -> Self {
+        if self.is_zero() || self.neg {
+            return Self::zero(self.prec());
+        }
+        // a = 0.m * 2^(e%2) in [0.25, 1), self = a * 4^(e/2)
+        let mut a = *self;
+        a.e = self.e.rem_euclid(2);
+        let half = Self::from_f64(0.5, self.prec());
+        let three_halves = Self::from_f64(1.5, self.prec());
+        let mut x = Self::from_f64(1. / a.to_f64().sqrt(), self.prec());
+        let mut bits = 50;
+        while bits < self.prec() + 64 {
+            x = x * (three_halves - half * a * x.square());
+            bits *= 2;
+        }
+        (a * x).mul_pow2(self.e.div_euclid(2))
+    }
+
+    /// builds a normalized and rounded value from a big endian buffer holding `0.buf * 2^e`
+    ///
+    /// `buf` needs at least `prec + 1` limbs, the first one after `prec` is used to round
+    fn pack(neg: bool, mut e: i64, buf: &[u64], prec: usize) -> Self {
+        let mut r = BigFloat { neg, e: 0, prec, m: [0; MAX_LIMBS] };
+        let first = match buf.iter().position(|&l| l != 0) {
+            Some(i) => i,
+            None => {
+                r.neg = false;
+                return r;
+            }
+        };
+        let shift = first * 64 + buf[first].leading_zeros() as usize;
+        let mut tmp = [0u64; 2 * MAX_LIMBS];
+        let tmp = &mut tmp[..buf.len()];
+        tmp.copy_from_slice(buf);
+        if shift > 0 {
+            shl_in_place(tmp, shift);
+            e -= shift as i64;
+        }
+        r.m[..prec].copy_from_slice(&tmp[..prec]);
+        if tmp.len() > prec && tmp[prec] >> 63 == 1 {
+            // round half up, carrying out of the top limb means the mantissa was all ones
+            let mut carry = true;
+            for l in r.m[..prec].iter_mut().rev() {
+                let (v, c) = l.overflowing_add(1);
+                *l = v;
+                carry = c;
+                if !c {break;}
+            }
+            if carry {
+                r.m[0] = 1 << 63;
+                e += 1;
+            }
+        }
+        r.e = e;
+        r
+    }
+
+    fn cmp_mag(&self, rhs: &Self) -> Ordering {
+        match (self.is_zero(), rhs.is_zero()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => self.e.cmp(&rhs.e).then_with(|| cmp_limbs(&self.m[..self.prec], &rhs.m[..rhs.prec])),
+        }
+    }
+
+    /// |self| + |rhs| or |self| - |rhs| with the sign of self, |self| has to be the biggest for a subtraction
+    fn add_mag(&self, rhs: &Self, sub: bool, prec: usize) -> Self {
+        let d = (self.e - rhs.e) as usize;
+        let mut a = [0u64; MAX_LIMBS + 1];
+        let mut b = [0u64; MAX_LIMBS + 1];
+        let a = &mut a[..prec + 1];
+        let b = &mut b[..prec + 1];
+        a[..self.prec].copy_from_slice(&self.m[..self.prec]);
+        shr_into(&rhs.m[..rhs.prec], d, b);
+
+        let mut e = self.e;
+        if sub {
+            let mut borrow = false;
+            for i in (0..prec + 1).rev() {
+                let (v, b1) = a[i].overflowing_sub(b[i]);
+                let (v, b2) = v.overflowing_sub(borrow as u64);
+                a[i] = v;
+                borrow = b1 || b2;
+            }
+        } else {
+            let mut carry = false;
+            for i in (0..prec + 1).rev() {
+                let (v, c1) = a[i].overflowing_add(b[i]);
+                let (v, c2) = v.overflowing_add(carry as u64);
+                a[i] = v;
+                carry = c1 || c2;
+            }
+            if carry {
+                let mut shifted = [0u64; MAX_LIMBS + 1];
+                shr_into(a, 1, &mut shifted[..prec + 1]);
+                a.copy_from_slice(&shifted[..prec + 1]);
+                a[0] |= 1 << 63;
+                e += 1;
+            }
+        }
+        Self::pack(self.neg, e, a, prec)
+    }
+
+    /// 10^n with `bits` of precision
+    fn pow10(n: i64, bits: usize) -> Self {
+        let mut r = Self::from_f64(1., bits);
+        let mut base = Self::from_f64(10., bits);
+        let mut k = n.unsigned_abs();
+        while k > 0 {
+            if k & 1 == 1 {
+                r = r * base;
+            }
+            base = base.square();
+            k >>= 1;
+        }
+        if n < 0 {r.recip()} else {r}
+    }
+
+    /// parses a decimal literal like `-1.25e-300` with `bits` of precision
+    pub fn parse(s: &str, bits: usize) -> Result<Self, ParseBigFloatError> {
+        let err = || ParseBigFloatError(s.to_string());
+        let t = s.trim();
+        let (neg, t) = match t.as_bytes().first() {
+            Some(b'-') => (true, &t[1..]),
+            Some(b'+') => (false, &t[1..]),
+            _ => (false, t),
+        };
+        let (mant, exp) = match t.find(['e', 'E']) {
+            Some(i) => (&t[..i], t[i + 1..].parse::<i64>().map_err(|_| err())?),
+            None => (t, 0),
+        };
+        let (int, frac) = mant.split_once('.').unwrap_or((mant, ""));
+        if int.is_empty() && frac.is_empty() {
+            return Err(err());
+        }
+
+        let work = bits + 64;
+        let ten = Self::from_f64(10., work);
+        let mut r = Self::zero(work);
+        for c in int.chars().chain(frac.chars()) {
+            let d = c.to_digit(10).ok_or_else(err)?;
+            r = r * ten + Self::from_f64(d as f64, work);
+        }
+        let exp = exp - frac.len() as i64;
+        if exp != 0 {
+            r = r * Self::pow10(exp, work);
+        }
+        r.neg = neg && !r.is_zero();
+        Ok(r.with_prec(bits))
+    }
+}
+
+impl From<f64> for BigFloat {
+    fn from(x: f64) -> Self {
+        Self::from_f64(x, 64)
+    }
+}
+
+impl FromStr for BigFloat {
+    type Err = ParseBigFloatError;
+
+    /// the precision is picked to hold every digit of the literal
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.chars().take_while(|c| *c != 'e' && *c != 'E').filter(|c| c.is_ascii_digit()).count();
+        Self::parse(s, (digits as f64 / LOG10_2) as usize + 64)
+    }
+}
+
+impl fmt::Display for BigFloat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        let work = self.prec() + 64;
+        // decimal exponent estimate from the binary one, fixed below if off by one
+        let log2 = (self.e - 1) as f64 + (self.m[0] as f64 / 9223372036854775808.).log2();
+        let mut k = (log2 * LOG10_2).floor() as i64;
+        let mut v = self.abs().with_prec(work) * Self::pow10(-k, work);
+        let ten = Self::from_f64(10., work);
+        let one = Self::from_f64(1., work);
+        while v >= ten {
+            v = v / ten;
+            k += 1;
+        }
+        while v < one {
+            v = v * ten;
+            k -= 1;
+        }
+
+        // v is in [1, 10), pull the integer part out of the mantissa bits
+        let n_digits = f.precision().map(|p| p + 1).unwrap_or((self.prec() as f64 * LOG10_2) as usize);
+        let mut digits = Vec::with_capacity(n_digits + 1);
+        for _ in 0..n_digits + 1 {
+            let d = if v.is_zero() || v.e <= 0 {0} else {v.m[0] >> (64 - v.e)};
+            digits.push(b'0' + d as u8);
+            v = (v - Self::from_f64(d as f64, work)) * ten;
+        }
+        // round on the extra digit, the scaling by 10^-k is not exact
+        if digits.pop().unwrap() >= b'5' {
+            let mut i = n_digits;
+            loop {
+                if i == 0 {
+                    digits.insert(0, b'1');
+                    digits.pop();
+                    k += 1;
+                    break;
+                }
+                i -= 1;
+                if digits[i] == b'9' {
+                    digits[i] = b'0';
+                } else {
+                    digits[i] += 1;
+                    break;
+                }
+            }
+        }
+        let digits = String::from_utf8(digits).unwrap();
+        let digits = if f.precision().is_some() {digits.as_str()} else {digits.trim_end_matches('0')};
+        let digits = if digits.is_empty() {"0"} else {digits};
+
+        let sign = if self.neg {"-"} else {""};
+        if (-8..20).contains(&k) && f.precision().is_none() {
+            if k >= 0 {
+                let k = k as usize;
+                let int = if digits.len() > k {&digits[..k + 1]} else {digits};
+                let zeros = (k + 1).saturating_sub(digits.len());
+                let frac = if digits.len() > k + 1 {&digits[k + 1..]} else {""};
+                write!(f, "{sign}{int}{}", "0".repeat(zeros))?;
+                if !frac.is_empty() {
+                    write!(f, ".{frac}")?;
+                }
+                Ok(())
+            } else {
+                write!(f, "{sign}0.{}{digits}", "0".repeat((-k - 1) as usize))
+            }
+        } else {
+            write!(f, "{sign}{}", &digits[..1])?;
+            if digits.len() > 1 {
+                write!(f, ".{}", &digits[1..])?;
+            }
+            write!(f, "e{k}")
+        }
+    }
+}
+
+impl fmt::Debug for BigFloat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl PartialEq for BigFloat {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for BigFloat {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(match (self.is_sign_negative(), other.is_sign_negative()) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => self.cmp_mag(other),
+            (true, true) => other.cmp_mag(self),
+        })
+    }
+}
+
+impl Add for BigFloat {
+    fn add(self, rhs: BigFloat) -> Self::Output {
+        let prec = self.prec.max(rhs.prec);
+        if rhs.is_zero() {
+            return self.with_prec(prec * 64);
+        }
+        if self.is_zero() {
+            return rhs.with_prec(prec * 64);
+        }
+        let sub = self.neg != rhs.neg;
+        if self.cmp_mag(&rhs) == Ordering::Less {
+            rhs.add_mag(&self, sub, prec)
+        } else {
+            self.add_mag(&rhs, sub, prec)
+        }
+    }
+    type Output = BigFloat;
+}
+
+impl Sub for BigFloat {
+    fn sub(self, rhs: BigFloat) -> Self::Output {
+        self + (-rhs)
+    }
+    type Output = BigFloat;
+}
+
+impl Neg for BigFloat {
+    fn neg(self) -> Self::Output {
+        let mut r = self;
+        r.neg = !r.neg;
+        r
+    }
+    type Output = BigFloat;
+}
+
+impl Mul for BigFloat {
+    fn mul(self, rhs: BigFloat) -> Self::Output {
+        let prec = self.prec.max(rhs.prec);
+        if self.is_zero() || rhs.is_zero() {
+            return Self::zero(prec * 64);
+        }
+        // schoolbook product, both mantissas are in [0.5, 1) so at most one leading zero bit
+        let (pa, pb) = (self.prec, rhs.prec);
+        let mut prod = [0u64; 2 * MAX_LIMBS];
+        for i in (0..pa).rev() {
+            let mut carry = 0u128;
+            for j in (0..pb).rev() {
+                let t = self.m[i] as u128 * rhs.m[j] as u128 + prod[i + j + 1] as u128 + carry;
+                prod[i + j + 1] = t as u64;
+                carry = t >> 64;
+            }
+            prod[i] = carry as u64;
+        }
+        let len = (pa + pb).max(prec + 1);
+        Self::pack(self.neg != rhs.neg, self.e + rhs.e, &prod[..len], prec)
+    }
+    type Output = BigFloat;
+}
+
+impl Div for BigFloat {
+    fn div(self, rhs: BigFloat) -> Self::Output {
+        let prec = self.prec.max(rhs.prec) * 64;
+        self.with_prec(prec) * rhs.with_prec(prec).recip()
+    }
+    type Output = BigFloat;
+}
+
+/// f64 mantissa with a separate exponent, value is `m * 2^e`
+///
+/// same precision as f64 but the range never runs out, used for the pixel offsets and
+/// derivatives of perturbation past ~1e-308
+#[derive(Clone, Copy, Debug)]
+pub struct FloatExp {
+    m: f64,
+    e: i64,
+}
+
+impl FloatExp {
+    /// `m` is brought back to [0.5, 1)
+    pub fn new(m: f64, e: i64) -> Self {
+        if m == 0. || !m.is_finite() {
+            return FloatExp { m, e: 0 };
+        }
+        let b = m.to_bits();
+        let biased = ((b >> 52) & 0x7FF) as i64;
+        if biased == 0 {
+            // subnormal, scale it up first
+            return Self::new(m * 18446744073709551616., e - 64);
+        }
+        FloatExp {
+            m: f64::from_bits(b & !(0x7FF << 52) | (1022 << 52)),
+            e: e + biased - 1022,
+        }
+    }
+
+    /// 2^e
+    pub const fn exp2(e: i64) -> Self {
+        FloatExp { m: 0.5, e: e + 1 }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        ldexp(self.m, self.e)
+    }
+
+    pub fn log2(&self) -> f64 {
+        self.m.abs().log2() + self.e as f64
+    }
+
+    pub fn sqrt(&self) -> Self {
+        if self.e % 2 == 0 {
+            Self::new(self.m.sqrt(), self.e / 2)
+        } else {
+            Self::new((self.m * 2.).sqrt(), (self.e - 1) / 2)
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        FloatExp { m: self.m.abs(), e: self.e }
+    }
+
+    /// self * 2^k, exact
+    pub fn mul_pow2(&self, k: i64) -> Self {
+        if self.m == 0. {*self} else {FloatExp { m: self.m, e: self.e + k }}
+    }
+}
+
+impl From<f64> for FloatExp {
+    #[inline]
+    fn from(x: f64) -> Self {
+        Self::new(x, 0)
+    }
+}
+
+impl PartialEq for FloatExp {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for FloatExp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (*self - *other).m.partial_cmp(&0.)
+    }
+}
+
+impl Add for FloatExp {
+    #[inline]
+    fn add(self, rhs: FloatExp) -> Self::Output {
+        if self.m == 0. {
+            return rhs;
+        }
+        if rhs.m == 0. {
+            return self;
+        }
+        let d = self.e - rhs.e;
+        if d > 64 {
+            self
+        } else if d < -64 {
+            rhs
+        } else if d >= 0 {
+            Self::new(self.m + rhs.m * 2f64.powi(-d as i32), self.e)
+        } else {
+            Self::new(self.m * 2f64.powi(d as i32) + rhs.m, rhs.e)
+        }
+    }
+    type Output = FloatExp;
+}
+
+impl Sub for FloatExp {
+    #[inline]
+    fn sub(self, rhs: FloatExp) -> Self::Output {
+        self + (-rhs)
+    }
+    type Output = FloatExp;
+}
+
+impl Neg for FloatExp {
+    #[inline]
+    fn neg(self) -> Self::Output {
+        FloatExp { m: -self.m, e: self.e }
+    }
+    type Output = FloatExp;
+}
+
+impl Mul for FloatExp {
+    #[inline]
+    fn mul(self, rhs: FloatExp) -> Self::Output {
+        Self::new(self.m * rhs.m, self.e + rhs.e)
+    }
+    type Output = FloatExp;
+}
+
+impl Div for FloatExp {
+    #[inline]
+    fn div(self, rhs: FloatExp) -> Self::Output {
+        Self::new(self.m / rhs.m, self.e - rhs.e)
+    }
+    type Output = FloatExp;
+}
+
+impl BigFloat {
+    pub fn from_floatexp(x: FloatExp, bits: usize) -> Self {
+        Self::from_f64(x.m, bits).mul_pow2(x.e)
+    }
+
+    pub fn to_floatexp(&self) -> FloatExp {
+        if self.is_zero() {
+            return FloatExp::from(0.);
+        }
+        let m = self.m[0] as f64 / 18446744073709551616.;
+        FloatExp::new(if self.neg {-m} else {m}, self.e)
+    }
+}
@@ -1,4 +1,7 @@
-#[derive(Copy, Clone)]
+use super::big_float::{BigFloat, FloatExp};
+use super::float::Float;
+
+#[derive(Copy, Clone, PartialEq, Default)]
 pub struct Cplx<T> {
     pub re: T,
     pub im: T,
@@ -92,15 +95,45 @@ impl<T: Copy + std::ops::Neg<Output = T>> std::ops::Neg for Cplx<T> {
     type Output = Cplx<T>;
 }
 
-impl Cplx<f64> {
-    pub fn square(&self) -> Cplx<f64> {
+impl<T: Float> Cplx<T> {
+    pub fn square(&self) -> Cplx<T> {
         // re*re-im*im, 2*re*im
         Cplx {
-            re: self.re * self.re - self.im * self.im,
-            im: 2. * self.re * self.im,
+            re: self.re.square() - self.im.square(),
+            im: self.re * self.im * T::from_f64(2.),
         }
     }
-    pub fn abs(&self) -> f64 {
+    pub fn abs(&self) -> T {
         self.sq_abs().sqrt()
     }
+    pub fn from_f64(z: Cplx<f64>) -> Cplx<T> {
+        Cplx {
+            re: T::from_f64(z.re),
+            im: T::from_f64(z.im),
+        }
+    }
+    pub fn to_f64(&self) -> Cplx<f64> {
+        Cplx {
+            re: self.re.to_f64(),
+            im: self.im.to_f64(),
+        }
+    }
+    pub fn from_floatexp(z: Cplx<FloatExp>) -> Cplx<T> {
+        Cplx {
+            re: T::from_floatexp(z.re),
+            im: T::from_floatexp(z.im),
+        }
+    }
+    pub fn to_floatexp(&self) -> Cplx<FloatExp> {
+        Cplx {
+            re: self.re.to_floatexp(),
+            im: self.im.to_floatexp(),
+        }
+    }
+    pub fn from_big(z: &Cplx<BigFloat>) -> Cplx<T> {
+        Cplx {
+            re: T::from_big(&z.re),
+            im: T::from_big(&z.im),
+        }
+    }
 }
@@ -0,0 +1,373 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use super::big_float::BigFloat;
+
+// error free transformations, see "Library for Double-Double and Quad-Double Arithmetic" (Hida, Li, Bailey)
+
+/// a + b = s + err exactly
+#[inline]
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+/// same as `two_sum` when |a| >= |b|
+#[inline]
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+/// splits in two halves of 26 bits, their products are exact
+#[inline]
+fn split(a: f64) -> (f64, f64) {
+    const SPLITTER: f64 = 134217729.; // 2^27 + 1
+    let t = SPLITTER * a;
+    let hi = t - (t - a);
+    (hi, a - hi)
+}
+
+/// a * b = p + err exactly, Dekker's product so it doesn't need a hardware fma
+#[inline]
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    let (ah, al) = split(a);
+    let (bh, bl) = split(b);
+    (p, ((ah * bh - p) + ah * bl + al * bh) + al * bl)
+}
+
+/// unevaluated sum of two f64, ~106 bits of mantissa
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+impl DoubleDouble {
+    pub fn from_big(x: &BigFloat) -> Self {
+        let hi = x.to_f64();
+        let lo = (*x - BigFloat::from_f64(hi, x.prec())).to_f64();
+        let (hi, lo) = quick_two_sum(hi, lo);
+        DoubleDouble { hi, lo }
+    }
+
+    #[inline]
+    pub fn to_f64(&self) -> f64 {
+        self.hi
+    }
+
+    #[inline]
+    pub fn square(&self) -> Self {
+        *self * *self
+    }
+
+    #[inline]
+    pub fn abs(&self) -> Self {
+        if self.hi < 0. {-*self} else {*self}
+    }
+
+    pub fn sqrt(&self) -> Self {
+        if self.hi <= 0. {
+            return DoubleDouble::from(0.);
+        }
+        // Karp's trick, one newton step from the f64 result
+        let x = 1. / self.hi.sqrt();
+        let ax = self.hi * x;
+        let (p, e) = two_prod(ax, ax);
+        let r = (*self - DoubleDouble { hi: p, lo: e }).hi;
+        let (hi, lo) = two_sum(ax, r * x * 0.5);
+        DoubleDouble { hi, lo }
+    }
+}
+
+impl From<f64> for DoubleDouble {
+    #[inline]
+    fn from(x: f64) -> Self {
+        DoubleDouble { hi: x, lo: 0. }
+    }
+}
+
+impl PartialEq for DoubleDouble {
+    fn eq(&self, other: &Self) -> bool {
+        self.hi == other.hi && self.lo == other.lo
+    }
+}
+
+impl PartialOrd for DoubleDouble {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.hi.partial_cmp(&other.hi) {
+            Some(Ordering::Equal) => self.lo.partial_cmp(&other.lo),
+            o => o,
+        }
+    }
+}
+
+impl Add for DoubleDouble {
+    #[inline]
+    fn add(self, rhs: DoubleDouble) -> Self::Output {
+        let (s, e) = two_sum(self.hi, rhs.hi);
+        let (t, f) = two_sum(self.lo, rhs.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        let (hi, lo) = quick_two_sum(s, e + f);
+        DoubleDouble { hi, lo }
+    }
+    type Output = DoubleDouble;
+}
+
+impl Sub for DoubleDouble {
+    #[inline]
+    fn sub(self, rhs: DoubleDouble) -> Self::Output {
+        self + (-rhs)
+    }
+    type Output = DoubleDouble;
+}
+
+impl Neg for DoubleDouble {
+    #[inline]
+    fn neg(self) -> Self::Output {
+        DoubleDouble { hi: -self.hi, lo: -self.lo }
+    }
+    type Output = DoubleDouble;
+}
+
+impl Mul for DoubleDouble {
+    #[inline]
+    fn mul(self, rhs: DoubleDouble) -> Self::Output {
+        let (p, e) = two_prod(self.hi, rhs.hi);
+        let (hi, lo) = quick_two_sum(p, e + (self.hi * rhs.lo + self.lo * rhs.hi));
+        DoubleDouble { hi, lo }
+    }
+    type Output = DoubleDouble;
+}
+
+impl Div for DoubleDouble {
+    #[inline]
+    fn div(self, rhs: DoubleDouble) -> Self::Output {
+        // long division, one f64 digit at a time
+        let q1 = self.hi / rhs.hi;
+        let r = self - rhs * q1.into();
+        let q2 = r.hi / rhs.hi;
+        let r = r - rhs * q2.into();
+        let q3 = r.hi / rhs.hi;
+        let (hi, lo) = quick_two_sum(q1, q2);
+        DoubleDouble { hi, lo } + q3.into()
+    }
+    type Output = DoubleDouble;
+}
+
+/// unevaluated sum of four f64, ~212 bits of mantissa
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QuadDouble {
+    c: [f64; 4],
+}
+
+/// a + b + c = a + b + c with the biggest part in a, the error pushed down
+#[inline]
+fn three_sum(a: f64, b: f64, c: f64) -> (f64, f64, f64) {
+    let (t1, t2) = two_sum(a, b);
+    let (a, t3) = two_sum(c, t1);
+    let (b, c) = two_sum(t2, t3);
+    (a, b, c)
+}
+
+#[inline]
+fn three_sum2(a: f64, b: f64, c: f64) -> (f64, f64) {
+    let (t1, t2) = two_sum(a, b);
+    let (a, t3) = two_sum(c, t1);
+    (a, t2 + t3)
+}
+
+/// brings five overlapping parts back to four non overlapping ones
+#[inline]
+fn renorm(c0: f64, c1: f64, c2: f64, c3: f64, c4: f64) -> QuadDouble {
+    if c0.is_infinite() {
+        return QuadDouble { c: [c0, c1, c2, c3] };
+    }
+    let (s0, c4) = quick_two_sum(c3, c4);
+    let (s0, c3) = quick_two_sum(c2, s0);
+    let (s0, c2) = quick_two_sum(c1, s0);
+    let (c0, c1) = quick_two_sum(c0, s0);
+
+    let mut s = [c0, c1, 0., 0.];
+    if s[1] != 0. {
+        (s[1], s[2]) = quick_two_sum(s[1], c2);
+        if s[2] != 0. {
+            (s[2], s[3]) = quick_two_sum(s[2], c3);
+            if s[3] != 0. {
+                s[3] += c4;
+            } else {
+                (s[2], s[3]) = quick_two_sum(s[2], c4);
+            }
+        } else {
+            (s[1], s[2]) = quick_two_sum(s[1], c3);
+            if s[2] != 0. {
+                (s[2], s[3]) = quick_two_sum(s[2], c4);
+            } else {
+                (s[1], s[2]) = quick_two_sum(s[1], c4);
+            }
+        }
+    } else {
+        (s[0], s[1]) = quick_two_sum(s[0], c2);
+        if s[1] != 0. {
+            (s[1], s[2]) = quick_two_sum(s[1], c3);
+            if s[2] != 0. {
+                (s[2], s[3]) = quick_two_sum(s[2], c4);
+            } else {
+                (s[1], s[2]) = quick_two_sum(s[1], c4);
+            }
+        } else {
+            (s[0], s[1]) = quick_two_sum(s[0], c3);
+            if s[1] != 0. {
+                (s[1], s[2]) = quick_two_sum(s[1], c4);
+            } else {
+                (s[0], s[1]) = quick_two_sum(s[0], c4);
+            }
+        }
+    }
+    QuadDouble { c: s }
+}
+
+impl QuadDouble {
+    pub fn from_big(x: &BigFloat) -> Self {
+        let mut r = *x;
+        let mut c = [0.; 4];
+        for c in c.iter_mut() {
+            *c = r.to_f64();
+            r = r - BigFloat::from_f64(*c, x.prec());
+        }
+        renorm(c[0], c[1], c[2], c[3], 0.)
+    }
+
+    #[inline]
+    pub fn to_f64(&self) -> f64 {
+        self.c[0]
+    }
+
+    #[inline]
+    pub fn square(&self) -> Self {
+        *self * *self
+    }
+
+    #[inline]
+    pub fn abs(&self) -> Self {
+        if self.c[0] < 0. {-*self} else {*self}
+    }
+
+    pub fn sqrt(&self) -> Self {
+        if self.c[0] <= 0. {
+            return QuadDouble::from(0.);
+        }
+        // newton on 1/sqrt(a), each step doubles the correct bits starting from 53
+        let half = QuadDouble::from(0.5);
+        let h = *self * half;
+        let mut x = QuadDouble::from(1. / self.c[0].sqrt());
+        for _ in 0..3 {
+            x = x + x * (half - h * x.square());
+        }
+        *self * x
+    }
+}
+
+impl From<f64> for QuadDouble {
+    #[inline]
+    fn from(x: f64) -> Self {
+        QuadDouble { c: [x, 0., 0., 0.] }
+    }
+}
+
+impl PartialEq for QuadDouble {
+    fn eq(&self, other: &Self) -> bool {
+        self.c == other.c
+    }
+}
+
+impl PartialOrd for QuadDouble {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        for i in 0..4 {
+            match self.c[i].partial_cmp(&other.c[i]) {
+                Some(Ordering::Equal) => (),
+                o => return o,
+            }
+        }
+        Some(Ordering::Equal)
+    }
+}
+
+impl Add for QuadDouble {
+    #[inline]
+    fn add(self, rhs: QuadDouble) -> Self::Output {
+        let (a, b) = (self.c, rhs.c);
+        let (s0, t0) = two_sum(a[0], b[0]);
+        let (s1, t1) = two_sum(a[1], b[1]);
+        let (s2, t2) = two_sum(a[2], b[2]);
+        let (s3, t3) = two_sum(a[3], b[3]);
+
+        let (s1, t0) = two_sum(s1, t0);
+        let (s2, t0, t1) = three_sum(s2, t0, t1);
+        let (s3, t0) = three_sum2(s3, t0, t2);
+        renorm(s0, s1, s2, s3, t0 + t1 + t3)
+    }
+    type Output = QuadDouble;
+}
+
+impl Sub for QuadDouble {
+    #[inline]
+    fn sub(self, rhs: QuadDouble) -> Self::Output {
+        self + (-rhs)
+    }
+    type Output = QuadDouble;
+}
+
+impl Neg for QuadDouble {
+    #[inline]
+    fn neg(self) -> Self::Output {
+        QuadDouble { c: self.c.map(|c| -c) }
+    }
+    type Output = QuadDouble;
+}
+
+impl Mul for QuadDouble {
+    #[inline]
+    fn mul(self, rhs: QuadDouble) -> Self::Output {
+        let (a, b) = (self.c, rhs.c);
+        let (p0, q0) = two_prod(a[0], b[0]);
+        let (p1, q1) = two_prod(a[0], b[1]);
+        let (p2, q2) = two_prod(a[1], b[0]);
+        let (p3, q3) = two_prod(a[0], b[2]);
+        let (p4, q4) = two_prod(a[1], b[1]);
+        let (p5, q5) = two_prod(a[2], b[0]);
+
+        let (p1, p2, q0) = three_sum(p1, p2, q0);
+
+        // six-three sum of p2, q1, q2, p3, p4, p5
+        let (p2, q1, q2) = three_sum(p2, q1, q2);
+        let (p3, p4, p5) = three_sum(p3, p4, p5);
+        let (s0, t0) = two_sum(p2, p3);
+        let (s1, t1) = two_sum(q1, p4);
+        let s2 = q2 + p5;
+        let (s1, t0) = two_sum(s1, t0);
+        let s2 = s2 + (t0 + t1);
+
+        // O(eps^3) terms
+        let s1 = s1 + a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + q0 + q3 + q4 + q5;
+        renorm(p0, p1, s0, s1, s2)
+    }
+    type Output = QuadDouble;
+}
+
+impl Div for QuadDouble {
+    #[inline]
+    fn div(self, rhs: QuadDouble) -> Self::Output {
+        let q0 = self.c[0] / rhs.c[0];
+        let r = self - rhs * q0.into();
+        let q1 = r.c[0] / rhs.c[0];
+        let r = r - rhs * q1.into();
+        let q2 = r.c[0] / rhs.c[0];
+        let r = r - rhs * q2.into();
+        let q3 = r.c[0] / rhs.c[0];
+        renorm(q0, q1, q2, q3, 0.)
+    }
+    type Output = QuadDouble;
+}
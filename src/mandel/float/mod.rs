@@ -0,0 +1,177 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use super::big_float::{BigFloat, FloatExp};
+use super::double_double::{DoubleDouble, QuadDouble};
+
+/// what the iteration needs from a real number type, so every precision runs the same code
+///
+/// conversions go through f64, `FloatExp` for values out of f64 range
+/// and `BigFloat` for values that need more precision than f64
+pub trait Float: Copy + PartialOrd
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self> {
+    /// relative rounding error of the operations
+    const EPSILON: f64;
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(&self) -> f64;
+    /// keeps as much of the precision of `x` as the type can hold
+    fn from_big(x: &BigFloat) -> Self;
+    fn from_floatexp(x: FloatExp) -> Self;
+    fn to_floatexp(&self) -> FloatExp {
+        self.to_f64().into()
+    }
+    fn sqrt(&self) -> Self;
+    #[inline]
+    fn square(&self) -> Self {
+        *self * *self
+    }
+}
+
+impl Float for f32 {
+    const EPSILON: f64 = f32::EPSILON as f64;
+    #[inline]
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+    fn from_big(x: &BigFloat) -> Self {
+        x.to_f64() as f32
+    }
+    #[inline]
+    fn from_floatexp(x: FloatExp) -> Self {
+        x.to_f64() as f32
+    }
+    #[inline]
+    fn sqrt(&self) -> Self {
+        f32::sqrt(*self)
+    }
+}
+
+impl Float for f64 {
+    const EPSILON: f64 = f64::EPSILON;
+    #[inline]
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+    fn from_big(x: &BigFloat) -> Self {
+        x.to_f64()
+    }
+    #[inline]
+    fn from_floatexp(x: FloatExp) -> Self {
+        x.to_f64()
+    }
+    #[inline]
+    fn sqrt(&self) -> Self {
+        f64::sqrt(*self)
+    }
+}
+
+impl Float for FloatExp {
+    const EPSILON: f64 = f64::EPSILON;
+    #[inline]
+    fn from_f64(x: f64) -> Self {
+        x.into()
+    }
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        FloatExp::to_f64(self)
+    }
+    fn from_big(x: &BigFloat) -> Self {
+        x.to_floatexp()
+    }
+    #[inline]
+    fn from_floatexp(x: FloatExp) -> Self {
+        x
+    }
+    #[inline]
+    fn to_floatexp(&self) -> FloatExp {
+        *self
+    }
+    #[inline]
+    fn sqrt(&self) -> Self {
+        FloatExp::sqrt(self)
+    }
+}
+
+impl Float for DoubleDouble {
+    const EPSILON: f64 = f64::EPSILON * f64::EPSILON / 4.;
+    #[inline]
+    fn from_f64(x: f64) -> Self {
+        x.into()
+    }
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        DoubleDouble::to_f64(self)
+    }
+    fn from_big(x: &BigFloat) -> Self {
+        DoubleDouble::from_big(x)
+    }
+    #[inline]
+    fn from_floatexp(x: FloatExp) -> Self {
+        x.to_f64().into()
+    }
+    #[inline]
+    fn sqrt(&self) -> Self {
+        DoubleDouble::sqrt(self)
+    }
+}
+
+impl Float for QuadDouble {
+    const EPSILON: f64 = f64::EPSILON * f64::EPSILON * f64::EPSILON * f64::EPSILON / 64.;
+    #[inline]
+    fn from_f64(x: f64) -> Self {
+        x.into()
+    }
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        QuadDouble::to_f64(self)
+    }
+    fn from_big(x: &BigFloat) -> Self {
+        QuadDouble::from_big(x)
+    }
+    #[inline]
+    fn from_floatexp(x: FloatExp) -> Self {
+        x.to_f64().into()
+    }
+    #[inline]
+    fn sqrt(&self) -> Self {
+        QuadDouble::sqrt(self)
+    }
+}
+
+/// values made from f64 or `FloatExp` get 64 bits, operations keep the precision of the most
+/// precise operand so they pick it up from the `BigFloat`s they meet
+impl Float for BigFloat {
+    /// of the default 64 bits
+    const EPSILON: f64 = 1. / 18446744073709551616.;
+    #[inline]
+    fn from_f64(x: f64) -> Self {
+        x.into()
+    }
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        BigFloat::to_f64(self)
+    }
+    fn from_big(x: &BigFloat) -> Self {
+        *x
+    }
+    fn from_floatexp(x: FloatExp) -> Self {
+        BigFloat::from_floatexp(x, 64)
+    }
+    fn to_floatexp(&self) -> FloatExp {
+        BigFloat::to_floatexp(self)
+    }
+    fn sqrt(&self) -> Self {
+        BigFloat::sqrt(self)
+    }
+    #[inline]
+    fn square(&self) -> Self {
+        BigFloat::square(self)
+    }
+}
@@ -0,0 +1,90 @@
+use super::cplx::Cplx;
+use super::float::Float;
+use super::Mandel;
+
+/// Newton steps at most, the orbit is already on the cycle so it takes 2 or 3
+const NEWTON_STEPS: usize = 16;
+
+/// squared relative size of the last Newton step once the cycle point is found
+const NEWTON_TOLERANCE: f64 = 1e-24;
+
+/// square root with the non-negative real part
+fn sqrt(z: Cplx<f64>) -> Cplx<f64> {
+    let r = z.abs();
+    let re = (0.5*(r + z.re)).sqrt();
+    let im = (0.5*(r - z.re)).sqrt();
+    Cplx{re, im: if z.im < 0. {-im} else {im}}
+}
+
+/// `f^p` and its derivatives at `z`, the ones the interior distance needs
+struct Cycle {
+    z: Cplx<f64>,
+    /// d/dz
+    dz: Cplx<f64>,
+    /// d/dc
+    dc: Cplx<f64>,
+    /// d²/dz²
+    dzdz: Cplx<f64>,
+    /// d²/dcdz
+    dcdz: Cplx<f64>,
+}
+
+impl Cycle {
+    fn new(z: Cplx<f64>, c: Cplx<f64>, period: usize) -> Self {
+        let one = Cplx{re: 1., im: 0.};
+        let zero = Cplx{re: 0., im: 0.};
+        let mut cycle = Cycle {z, dz: one, dc: zero, dzdz: zero, dcdz: zero};
+        for _ in 0..period {
+            let Cycle {z, dz, dc, dzdz, dcdz} = cycle;
+            cycle = Cycle {
+                z: z.square() + c,
+                dz: dz*z*2.,
+                dc: dc*z*2. + one,
+                dzdz: (dz*dz + z*dzdz)*2.,
+                dcdz: (dc*dz + z*dcdz)*2.,
+            };
+        }
+        cycle
+    }
+}
+
+impl<T: Float> Mandel<T> {
+    /// a point on or near the attracting cycle, the last `z` or the closed form for the main bulbs
+    fn cycle_start(&self, c: Cplx<f64>) -> Cplx<f64> {
+        let one = Cplx{re: 1., im: 0.};
+        let half = Cplx{re: 0.5, im: 0.};
+        if self.z.re.is_finite() && self.z.im.is_finite() {
+            return self.z;
+        }
+        match self.period {
+            // z² + c = z
+            1 => (one - sqrt(one - c*4.))*0.5,
+            // z² + z + c + 1 = 0, the other root of z² - z + c is the repelling fixed point
+            _ => sqrt(-(c*4.) - Cplx{re: 3., im: 0.})*0.5 - half,
+        }
+    }
+
+    /// multiplier and interior distance of the cycle found while iterating
+    ///
+    /// the orbit only comes near the cycle, Newton on `f^p(z) - z` brings it onto it. the distance
+    /// is `(1 - |dz|²) / |dcdz + dzdz*dc/(1 - dz)|` with the derivatives of `f^p` at that point
+    pub(super) fn solve_interior(&mut self) {
+        let one = Cplx{re: 1., im: 0.};
+        let c = self.c.to_f64();
+        let mut z = self.cycle_start(c);
+        for _ in 0..NEWTON_STEPS {
+            let cycle = Cycle::new(z, c, self.period);
+            let step = (cycle.z - z)/(cycle.dz - one);
+            z = z - step;
+            // NaN too, going on won't help then
+            let size = step.sq_abs();
+            if size.is_nan() || size <= NEWTON_TOLERANCE*z.sq_abs() {
+                break;
+            }
+        }
+        let cycle = Cycle::new(z, c, self.period);
+        self.multiplier = cycle.dz;
+        let d = cycle.dcdz + cycle.dzdz*cycle.dc/(one - cycle.dz);
+        self.interior_distance = (1. - cycle.dz.sq_abs()) / d.abs();
+    }
+}
@@ -1,36 +1,146 @@
+pub mod average;
 pub mod cplx;
+pub mod big_float;
+pub mod double_double;
+pub mod float;
+pub mod interior;
+pub mod perturbation;
+pub mod simd;
+pub mod trap;
+use average::Averages;
+use big_float::FloatExp;
 use cplx::Cplx;
+use float::Float;
+use trap::Trap;
 
+/// escape radius, big so the smooth iteration count is accurate
+const M: f64 = 32.;
+
+/// the orbit is periodic once it comes back within this fraction of a pixel of a saved point
+const PERIOD_TOLERANCE: f64 = 1./256.;
+/// but never closer than this many `EPSILON`s, rounding alone moves it that much
+const PERIOD_ULPS: f64 = 16.;
+
+/// one point iterated with the number type `T`, results are kept in f64 whatever `T` is
 #[derive(Clone, Copy)]
-pub struct Mandel {
-    c: Cplx<f64>,
+pub struct Mandel<T: Float = f64> {
+    c: Cplx<T>,
     n: f64,
     normal: Cplx<f64>,
+    /// last value of the orbit, at escape or after `n_max` iterations
+    z: Cplx<f64>,
+    /// of `z` with respect to `c`, it outgrows f64 near the boundary
+    derivative: Cplx<FloatExp>,
+    /// exterior distance estimate `|z|·ln|z|/|dz|`, NaN unless the point escaped
+    distance: FloatExp,
     n_max: usize,
+    /// distance to the next pixel, the cycle detection goes down to a fraction of it
+    pixel: f64,
+    /// period of the cycle the orbit fell into, 0 when none was found
+    period: usize,
+    /// derivative of `f^period` on the cycle, its size tells how strongly it attracts
+    multiplier: Cplx<f64>,
+    /// interior distance estimate, NaN without a cycle
+    interior_distance: f64,
+    glitched: bool,
+    corrected: bool,
+    /// taken as inside because the border around it is, never iterated
+    filled: bool,
+    skipped: usize,
+    trap: Option<Trap>,
+    /// closest the orbit came to the trap, in trap sizes
+    trap_distance: f64,
+    /// iteration where it was that close
+    trap_iteration: usize,
+    /// sums of the average colorings, when they're wanted
+    averages: Option<Averages>,
 }
 
-impl Mandel {
-    pub fn new(c: Cplx<f64>, n_max: usize) -> Self {
+impl<T: Float> Mandel<T> {
+    /// `trap` is checked and the `averages` are summed at every iteration, the simd kernels and
+    /// the skips of perturbation are off then
+    pub fn new(c: Cplx<T>, n_max: usize, pixel: f64, trap: Option<Trap>, averages: bool) -> Self {
         Mandel {
             c,
             n_max,
+            pixel,
+            period: 0,
+            multiplier: Cplx{re:f64::NAN, im:f64::NAN},
+            interior_distance: f64::NAN,
             normal: Cplx{re:f64::NAN, im:f64::NAN},
             n: f64::NAN,
+            z: Cplx{re:f64::NAN, im:f64::NAN},
+            derivative: Cplx{re:f64::NAN.into(), im:f64::NAN.into()},
+            distance: f64::NAN.into(),
+            glitched: false,
+            corrected: false,
+            filled: false,
+            skipped: 0,
+            trap,
+            trap_distance: f64::INFINITY,
+            trap_iteration: 0,
+            averages: if averages {Some(Averages::default())} else {None},
         }
     }
+    /// inside the set without being iterated, it has no period, multiplier or trap
+    pub fn new_filled(c: Cplx<T>, n_max: usize) -> Self {
+        let mut m = Self::new(c, n_max, 0., None, false);
+        m.n = f64::INFINITY;
+        m.filled = true;
+        m
+    }
     pub fn new_empty() -> Self {
         Mandel {
-            c: Cplx::<f64> { re: 0., im: 0. },
+            c: Cplx { re: T::from_f64(0.), im: T::from_f64(0.) },
             n_max: 256,
+            pixel: 0.,
+            period: 0,
+            multiplier: Cplx{re:f64::NAN, im:f64::NAN},
+            interior_distance: f64::NAN,
             normal: Cplx{re:f64::NAN, im:f64::NAN},
             n: f64::NAN,
+            z: Cplx{re:f64::NAN, im:f64::NAN},
+            derivative: Cplx{re:f64::NAN.into(), im:f64::NAN.into()},
+            distance: f64::NAN.into(),
+            glitched: false,
+            corrected: false,
+            filled: false,
+            skipped: 0,
+            trap: None,
+            trap_distance: f64::INFINITY,
+            trap_iteration: 0,
+            averages: None,
+        }
+    }
+    /// same results with the point rounded to f64, so every precision ends up in the same buffers
+    pub fn to_f64(&self) -> Mandel {
+        Mandel {
+            c: self.c.to_f64(),
+            n: self.n,
+            normal: self.normal,
+            z: self.z,
+            derivative: self.derivative,
+            distance: self.distance,
+            n_max: self.n_max,
+            pixel: self.pixel,
+            period: self.period,
+            multiplier: self.multiplier,
+            interior_distance: self.interior_distance,
+            glitched: self.glitched,
+            corrected: self.corrected,
+            filled: self.filled,
+            skipped: self.skipped,
+            trap: self.trap,
+            trap_distance: self.trap_distance,
+            trap_iteration: self.trap_iteration,
+            averages: self.averages,
         }
     }
     #[inline]
     pub fn get_mandel(&self) -> usize {
         let mut z = self.c;
         for n in 1..self.n_max {
-            if z.sq_abs() >= 4. {
+            if z.sq_abs() >= T::from_f64(4.) {
                 return n;
             }
             z = z.square() + self.c;
@@ -56,30 +166,199 @@ impl Mandel {
         }
     }
 
+    /// last `z` of the orbit
+    #[inline]
+    pub fn get_z(&self) -> Cplx<f64> {
+        self.z
+    }
+
+    /// derivative of the last `z` with respect to `c`
+    #[inline]
+    pub fn get_derivative(&self) -> Cplx<FloatExp> {
+        self.derivative
+    }
+
+    /// estimated distance from `c` to the set, for escaped points
+    ///
+    /// close to the set the true distance is between half of it and twice it, further out the
+    /// lower bound drops, see `get_distance_bound`
+    #[inline]
+    pub fn get_distance(&self) -> Option<FloatExp> {
+        if self.n.is_finite() {Some(self.distance)} else {None}
+    }
+
+    /// distance from `c` under which there's no point of the set, for escaped points
+    ///
+    /// Koebe 1/4 on the Green's function `G = ln|z_n|/2^(n-1)`, `z_1 = c`, gives `(1 - e^(-2G))/(4|G'|)`, the
+    /// estimate is `G/|G'|` so that's `(1 - e^(-2G))/(4G)` of it: half as `G` goes to 0 and less
+    /// than a quarter for the points that escape in a couple of iterations
+    pub fn get_distance_bound(&self) -> Option<FloatExp> {
+        let distance = self.get_distance()?;
+        let ln_r = self.z.abs().ln();
+        // the escape iteration back from the smooth count
+        let n = (self.n + fast_log2(0.5 * fast_ln(self.z.sq_abs()))).round();
+        let g = ln_r * (1. - n).exp2();
+        let factor = if g > 0. {-(-2.*g).exp_m1() / (4.*g)} else {0.5};
+        Some(distance * FloatExp::from(factor))
+    }
+
+    /// period of the attracting cycle of an interior point, when it was found
+    #[inline]
+    pub fn get_period(&self) -> Option<usize> {
+        if self.period > 0 {Some(self.period)} else {None}
+    }
+
+    /// multiplier of the attracting cycle, when its period was found
+    #[inline]
+    pub fn get_multiplier(&self) -> Option<Cplx<f64>> {
+        self.get_period().map(|_| self.multiplier)
+    }
+
+    /// estimated distance from `c` to the boundary of the component it's in, when its period was found
+    #[inline]
+    pub fn get_interior_distance(&self) -> Option<f64> {
+        self.get_period().map(|_| self.interior_distance)
+    }
+
+    /// perturbation lost precision against the reference orbit for this pixel
+    #[inline]
+    pub fn get_glitched(&self) -> bool {
+        self.glitched
+    }
+
+    /// every glitch of the pixel was followed by a rebase, the result can be trusted
+    #[inline]
+    pub fn get_corrected(&self) -> bool {
+        self.corrected
+    }
+
+    /// closest distance of the orbit to the trap, in trap sizes, and the iteration it happened
+    /// at. `None` without trap or when a stalk never caught the orbit
+    #[inline]
+    pub fn get_trap(&self) -> Option<(f64, usize)> {
+        if self.trap_distance.is_finite() {Some((self.trap_distance, self.trap_iteration))} else {None}
+    }
+
+    /// triangle inequality, stripe and curvature averages of an escaped orbit, when they were asked for
+    #[inline]
+    pub fn get_averages(&self) -> Option<Averages> {
+        if self.n.is_finite() {self.averages} else {None}
+    }
+
+    /// the point is inside because the border around it is, it wasn't iterated
+    #[inline]
+    pub fn get_filled(&self) -> bool {
+        self.filled
+    }
+
+    /// iterations jumped over by the perturbation's linear approximations
+    #[inline]
+    pub fn get_skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// period of the main cardioid (1) or of the period 2 bulb when `c` is in one of them, it
+    /// never escapes then
+    ///
+    /// with `x = re - 1/4` the cardioid is `q*(q + x) <= im²/4` where `q = x² + im²`,
+    /// the bulb is the disk of radius 1/4 around -1
+    #[inline]
+    fn main_bulb_period(&self) -> Option<usize> {
+        let quarter = T::from_f64(0.25);
+        let im2 = self.c.im.square();
+        let x = self.c.re - quarter;
+        let q = x.square() + im2;
+        if q*(q + x) <= quarter*im2 {
+            return Some(1);
+        }
+        if (self.c.re + T::from_f64(1.)).square() + im2 <= T::from_f64(1./16.) {
+            return Some(2);
+        }
+        None
+    }
+
+    /// squared distance under which two points of the orbit are the same
+    #[inline]
+    fn period_tolerance(&self) -> T {
+        T::from_f64((PERIOD_TOLERANCE*self.pixel).max(PERIOD_ULPS*T::EPSILON).powi(2))
+    }
+
+    /// iterates until escape, `n_max`, or until the orbit is caught in a cycle
+    ///
+    /// cycles are found the way Brent does: the orbit is saved at every power of two and
+    /// compared to that point on every step, as the saved point gets further back in the orbit
+    /// every period is caught within a few times its length once the orbit has converged
     #[inline]
     pub fn calculate_mandel_smooth(&mut self) {
+        // these would run to n_max, unless the trap needs their orbit
+        if let Some(period) = self.main_bulb_period().filter(|_| self.trap.is_none()) {
+            self.n = f64::INFINITY;
+            self.period = period;
+            self.solve_interior();
+            return;
+        }
+        let two = T::from_f64(2.);
+        let bailout = T::from_f64(M * M);
+        let tolerance = self.period_tolerance();
         let mut z = self.c;
         // let mut z = Cplx{re:0.,im:0.};
-        const M: f64 = 32.;
-        let mut derivative = Cplx{re:1., im:0.};
+        let one = Cplx{re:T::from_f64(1.), im:T::from_f64(0.)};
+        let mut derivative = one;
+        let mut saved = z;
+        let mut saved_at = 1;
         for i in 1..self.n_max {
-            if z.sq_abs() >= M * M {
+            self.check_trap(&z, i);
+            self.add_averages(&z);
+            if z.sq_abs() >= bailout {
                 self.n = i as f64;
                 break;
             }
             // if derivative.sq_abs() <= 0.00001 {
             //     break;
             // }
-            derivative = derivative*z*2.;
+            derivative = derivative*z*two + one;
             z = z.square() + self.c;
+            // z is z_(i+1)
+            if (z - saved).sq_abs() <= tolerance {
+                self.period = i + 1 - saved_at;
+                break;
+            }
+            if (i + 1).is_power_of_two() {
+                saved = z;
+                saved_at = i + 1;
+            }
         }
+        self.finish_derivative(z, derivative);
+    }
+
+    /// `finish` with the final `z` and derivative still in `T`
+    #[inline]
+    fn finish_derivative(&mut self, z: Cplx<T>, derivative: Cplx<T>) {
+        // the derivative can be way out of f64 range, normalize before converting
+        let normal = z/derivative;
+        self.finish(z.to_f64(), (normal/normal.abs()).to_f64(), derivative.to_floatexp());
+    }
+
+    /// smooth iteration count and normal from the state at escape, `n` is the escape iteration or NaN
+    ///
+    /// `normal` is `z/derivative` normalized
+    #[inline]
+    fn finish(&mut self, z: Cplx<f64>, normal: Cplx<f64>, derivative: Cplx<FloatExp>) {
+        self.z = z;
+        self.derivative = derivative;
         if self.n.is_nan() {
             self.n = f64::INFINITY;
+            if self.period > 0 {
+                self.solve_interior();
+            }
         } else {
 
             // n - fast_log2(0.5*fast_ln(z.sq_abs()))
-            self.normal = z/derivative;
-            self.normal = self.normal/self.normal.abs();
+            self.normal = normal;
+            self.finish_averages(z);
+            let r = z.abs();
+            // the derivative can be way out of f64 range, so can the distance
+            self.distance = FloatExp::from(r*r.ln()) / derivative.abs();
 
             self.n -= fast_log2(0.5 * fast_ln(z.sq_abs()));
             // self.n -= (0.5 * (z.sq_abs()).ln()).log2();
@@ -92,12 +371,12 @@ impl Mandel {
     }
 
     #[inline]
-    pub fn get_julia_smooth(&self, c: Cplx<f64>) -> f64 {
+    pub fn get_julia_smooth(&self, c: Cplx<T>) -> f64 {
         let mut z = self.c;
         let mut n = 0.;
         const M: f64 = 10.;
         for i in 1..self.n_max {
-            if z.sq_abs() >= M * M {
+            if z.sq_abs() >= T::from_f64(M * M) {
                 n = i as f64;
                 break;
             }
@@ -108,7 +387,7 @@ impl Mandel {
         }
 
         // n - fast_log2(0.5*fast_ln(z.sq_abs()))
-        n - fast_log2(0.5 * fast_ln(z.sq_abs()))
+        n - fast_log2(0.5 * fast_ln(z.sq_abs().to_f64()))
         // N + 1 + 1/ln(p)*ln(ln(M)/ln(r)) //M = big escape value, p = power (2 here), r = radius at escape
         // => N + 1 + log2(ln(M)/ln(r))
         // => N + 1 + log2(ln(M)) - log2(ln(r)) //we can get rid of constants, they are just a shift
@@ -0,0 +1,220 @@
+use super::big_float::{BigFloat, FloatExp};
+use super::cplx::Cplx;
+use super::float::Float;
+use super::{Mandel, M};
+
+/// squared, a pixel is glitched when `|z| < 1e-3 |Z|`
+const GLITCH_TOLERANCE: f64 = 1e-6;
+
+/// iterations of the reference orbit between two checks for cancellation
+const CANCEL_CHECK_ITERATIONS: usize = 256;
+
+/// relative size of the `dz²` term dropped by a skip, half an ulp so it is never bigger than
+/// the rounding error of iterating normally
+const BLA_EPSILON: f64 = f64::EPSILON / 2.;
+
+/// linear approximation of `l` iterations of the offset: `dz -> a*dz + b*dc`
+///
+/// one step is `dz -> 2*Z*dz + dz² + dc`, so `a = 2*Z` and `b = 1` as long as `|dz²|` stays under
+/// `BLA_EPSILON * |2*Z*dz|`, that is `|dz| < BLA_EPSILON * |2*Z|`.
+/// two steps are merged by composing them, the radius is shrunk so the offset after the first one
+/// (at most `|a1|*r + |b1|*|dc|`) is still inside the radius of the second one.
+/// every single iteration of a skip then keeps its error under `BLA_EPSILON` of its linear term.
+#[derive(Clone, Copy)]
+struct Bla {
+    a: Cplx<FloatExp>,
+    b: Cplx<FloatExp>,
+    /// squared validity radius for `dz`
+    r2: FloatExp,
+}
+
+fn fe_min(a: FloatExp, b: FloatExp) -> FloatExp {
+    if a < b {a} else {b}
+}
+
+impl Bla {
+    fn step(z: Cplx<f64>) -> Self {
+        let a = Cplx::<FloatExp>::from_f64(z*2.);
+        // also stay under the escape radius, escapes are only checked between skips
+        let r = fe_min(a.abs()*BLA_EPSILON.into(), FloatExp::from(M - z.abs()));
+        Bla {
+            a,
+            b: Cplx::<FloatExp>::from_f64(Cplx{re:1., im:0.}),
+            r2: if r > 0.0.into() {r*r} else {0.0.into()},
+        }
+    }
+
+    /// `self` then `next`, `dc` is the biggest pixel offset of the view
+    fn merge(&self, next: &Self, dc: FloatExp) -> Self {
+        let r = next.r2.sqrt() - self.b.abs()*dc;
+        let r = if r > 0.0.into() {r / self.a.abs()} else {0.0.into()};
+        Bla {
+            a: next.a*self.a,
+            b: next.a*self.b + next.b,
+            r2: fe_min(self.r2, r*r),
+        }
+    }
+}
+
+/// orbit of the view center, computed at full precision and rounded to f64
+///
+/// pixels then only iterate their offset `dz` from it:
+/// `z + dz -> (z + dz)² + c + dc` gives `dz -> 2*z*dz + dz² + dc`,
+/// which stays accurate in f64 because it never adds the tiny `dz` to a big `z`
+pub struct Reference {
+    c: Cplx<f64>,
+    z: Vec<Cplx<f64>>,
+    /// `bla[k][j]` skips `2^k` iterations starting at `Z[1 + j*2^k]`
+    bla: Vec<Vec<Bla>>,
+}
+
+impl Reference {
+    /// orbit computed with `T`, which has to hold `c` to a fraction of a pixel
+    ///
+    /// `radius` is the biggest pixel offset from `c` in the view, it bounds the skip tables.
+    /// `cancelled` is asked every `CANCEL_CHECK_ITERATIONS` and between skip levels, `None` once it says yes
+    pub fn new<T: Float>(c: Cplx<BigFloat>, n_max: usize, radius: FloatExp, cancelled: impl Fn() -> bool) -> Option<Self> {
+        let ct = Cplx::<T>::from_big(&c);
+        let mut z = Cplx{re: T::from_f64(0.), im: T::from_f64(0.)};
+        let mut orbit = Vec::with_capacity(n_max);
+        for i in 0..n_max {
+            if i % CANCEL_CHECK_ITERATIONS == 0 && cancelled() {
+                return None;
+            }
+            let zf = z.to_f64();
+            orbit.push(zf);
+            if zf.sq_abs() >= M * M {
+                break;
+            }
+            z = z.square() + ct;
+        }
+        let bla = Self::build_bla(&orbit, radius, &cancelled)?;
+        Some(Reference { c: c.to_f64(), z: orbit, bla })
+    }
+
+    fn build_bla(orbit: &[Cplx<f64>], radius: FloatExp, cancelled: impl Fn() -> bool) -> Option<Vec<Vec<Bla>>> {
+        // Z_0 = 0 can't be linearized, the last value has no next step
+        if orbit.len() < 3 {
+            return Some(Vec::new());
+        }
+        let mut levels = vec![orbit[1..orbit.len() - 1].iter().map(|&z| Bla::step(z)).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            if cancelled() {
+                return None;
+            }
+            let prev = levels.last().unwrap();
+            let next = prev.chunks_exact(2).map(|p| p[0].merge(&p[1], radius)).collect();
+            levels.push(next);
+        }
+        Some(levels)
+    }
+
+    /// biggest skip valid for `dz` at `Z[m]`, not longer than `max`
+    #[inline]
+    fn bla<D: Float>(&self, m: usize, dz: &Cplx<D>, max: usize) -> Option<(usize, &Bla)> {
+        if m == 0 {
+            return None;
+        }
+        let dz2 = dz.sq_abs();
+        for k in (0..self.bla.len()).rev() {
+            let l = 1 << k;
+            if !(m - 1).is_multiple_of(l) || l > max {
+                continue;
+            }
+            match self.bla[k].get((m - 1) >> k) {
+                Some(bla) if dz2 < D::from_floatexp(bla.r2) => return Some((l, bla)),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// number of iterations stored, less than `n_max` when the center escapes
+    #[inline]
+    pub fn iterations(&self) -> usize {
+        self.z.len()
+    }
+}
+
+impl Mandel {
+    /// same result as `calculate_mandel_smooth` for the point `reference.c + dc`
+    ///
+    /// when `z` gets smaller than `dz` the offset can't be trusted anymore (that's where the flat
+    /// blobs come from), the pixel is then rebased: the reference restarts at 0 and `z` itself
+    /// becomes the offset, which is exact since `Z_0 = 0`.
+    /// the same is done when the pixel outlives a reference that escaped.
+    ///
+    /// whenever `dz` is small enough, whole blocks of iterations are skipped with the reference's
+    /// linear approximations.
+    ///
+    /// there's no cycle detection here, the cycle could only be solved in f64 from a `c` rounded
+    /// way past the pixel spacing.
+    #[inline]
+    pub fn calculate_perturbed<D: Float>(&mut self, reference: &Reference, dc: Cplx<D>) {
+        let orbit = &reference.z;
+        let two = D::from_f64(2.);
+        let bailout = D::from_f64(M * M);
+        let tolerance = D::from_f64(GLITCH_TOLERANCE);
+        // orbit[0] is 0, so z_1 = c is orbit[1] + dc
+        let mut dz = dc;
+        let mut m = 1;
+        if orbit.len() < 2 {
+            m = 0;
+            dz = Cplx::from_f64(reference.c) + dc;
+        }
+        let mut z = Cplx::from_f64(orbit[m]) + dz;
+        let one = Cplx{re: D::from_f64(1.), im: D::from_f64(0.)};
+        let mut derivative = one;
+        // a trap and the averages have to see every iteration
+        let skip = self.trap.is_none() && self.averages.is_none();
+        // a glitch found and not rebased yet, the criterion implies `|z| < |dz|` so the rebase
+        // comes in the same iteration and the pixel goes on exactly from there
+        let mut unfixed = false;
+        let mut i = 1;
+        while i < self.n_max {
+            self.check_trap(&z, i);
+            self.add_averages(&z);
+            if z.sq_abs() >= bailout {
+                self.n = i as f64;
+                break;
+            }
+            if let Some((l, bla)) = reference.bla(m, &dz, self.n_max - i).filter(|_| skip) {
+                let a = Cplx::<D>::from_floatexp(bla.a);
+                let b = Cplx::<D>::from_floatexp(bla.b);
+                dz = a*dz + b*dc;
+                // 2*z is within BLA_EPSILON of 2*Z too, the derivative follows dz
+                derivative = derivative*a + b;
+                m += l;
+                i += l;
+                self.skipped += l;
+                z = Cplx::from_f64(orbit[m]) + dz;
+                continue;
+            }
+            derivative = derivative*z*two + one;
+            if m + 1 >= orbit.len() {
+                dz = z;
+                m = 0;
+            }
+            let zm = Cplx::<D>::from_f64(orbit[m]);
+            dz = (zm*two + dz)*dz + dc;
+            m += 1;
+            let zm = Cplx::<D>::from_f64(orbit[m]);
+            z = zm + dz;
+            // Pauldelbrot's criterion, the pixel left the neighbourhood of the reference
+            if z.sq_abs() < tolerance * zm.sq_abs() {
+                self.glitched = true;
+                unfixed = true;
+            }
+            if z.sq_abs() < dz.sq_abs() {
+                dz = z;
+                m = 0;
+                unfixed = false;
+            }
+            i += 1;
+        }
+        self.corrected = self.glitched && !unfixed;
+        // the derivative can be way out of f64 range, normalize before converting
+        let normal = z/derivative;
+        self.finish(z.to_f64(), (normal/normal.abs()).to_f64(), derivative.to_floatexp());
+    }
+}
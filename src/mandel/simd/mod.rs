@@ -0,0 +1,506 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use super::cplx::Cplx;
+use super::float::Float;
+use super::{Mandel, M};
+
+/// instruction set the batches are iterated with, picked at runtime
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kernel {
+    Avx2,
+    Sse2,
+    Scalar,
+}
+
+impl Kernel {
+    /// best kernel the cpu supports, the result is cached by std so it's cheap to call per batch
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Kernel::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Kernel::Sse2;
+            }
+        }
+        Kernel::Scalar
+    }
+}
+
+impl std::fmt::Display for Kernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Kernel::Avx2 => write!(f, "avx2"),
+            Kernel::Sse2 => write!(f, "sse2"),
+            Kernel::Scalar => write!(f, "scalar"),
+        }
+    }
+}
+
+/// number types with a vectorized `calculate_mandel_smooth`
+pub trait SimdFloat: Float {
+    fn calculate_with(kernel: Kernel, mandels: &mut [Mandel<Self>]);
+}
+
+/// `calculate_mandel_smooth` on every point, several points per instruction
+///
+/// the points all have to share the same `n_max` and pixel size.
+/// the lanes do exactly the operations of the scalar code in the same order (no fma), so the
+/// results are bit for bit the same. orbit traps and averages are only done by the scalar code
+pub fn calculate_mandel_smooth<T: SimdFloat>(mandels: &mut [Mandel<T>]) {
+    if mandels.iter().any(|m| m.trap.is_some() || m.averages.is_some()) {
+        scalar(mandels);
+        return;
+    }
+    T::calculate_with(Kernel::detect(), mandels);
+}
+
+fn scalar<T: Float>(mandels: &mut [Mandel<T>]) {
+    for m in mandels {
+        m.calculate_mandel_smooth();
+    }
+}
+
+impl SimdFloat for f64 {
+    fn calculate_with(kernel: Kernel, mandels: &mut [Mandel<f64>]) {
+        match kernel {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { avx2_f64(mandels) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => unsafe { sse2_f64(mandels) },
+            _ => scalar(mandels),
+        }
+    }
+}
+
+impl SimdFloat for f32 {
+    fn calculate_with(kernel: Kernel, mandels: &mut [Mandel<f32>]) {
+        match kernel {
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { avx2_f32(mandels) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => unsafe { sse2_f32(mandels) },
+            _ => scalar(mandels),
+        }
+    }
+}
+
+// the generic code below is inlined into these, so the intrinsics get compiled with the feature on
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn avx2_f64(mandels: &mut [Mandel<f64>]) {
+    calculate_lanes::<__m256d>(mandels);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn sse2_f64(mandels: &mut [Mandel<f64>]) {
+    calculate_lanes::<__m128d>(mandels);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn avx2_f32(mandels: &mut [Mandel<f32>]) {
+    calculate_lanes::<__m256>(mandels);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn sse2_f32(mandels: &mut [Mandel<f32>]) {
+    calculate_lanes::<__m128>(mandels);
+}
+
+/// a vector of `N` floats, masks are vectors too with every bit of a lane set for true
+///
+/// only meant to be used inlined in a function enabling the matching target feature
+trait Lanes: Copy {
+    type S: Float;
+    const N: usize;
+    unsafe fn splat(x: Self::S) -> Self;
+    /// the `N` first values of `x`
+    unsafe fn load(x: &[Self::S]) -> Self;
+    unsafe fn store(self, x: &mut [Self::S]);
+    unsafe fn add(self, rhs: Self) -> Self;
+    unsafe fn sub(self, rhs: Self) -> Self;
+    unsafe fn mul(self, rhs: Self) -> Self;
+    /// mask of `self >= rhs`, false for NaN
+    unsafe fn ge(self, rhs: Self) -> Self;
+    unsafe fn or(self, rhs: Self) -> Self;
+    /// `!self & rhs`
+    unsafe fn andnot(self, rhs: Self) -> Self;
+    /// `b` where `self` is set, `a` elsewhere
+    unsafe fn select(self, a: Self, b: Self) -> Self;
+    /// bit `i` is lane `i` of the mask
+    unsafe fn bits(self) -> u32;
+}
+
+#[inline(always)]
+unsafe fn calculate_lanes<L: Lanes>(mandels: &mut [Mandel<L::S>]) {
+    let mut chunks = mandels.chunks_exact_mut(L::N);
+    for chunk in &mut chunks {
+        iterate::<L>(chunk);
+    }
+    scalar(chunks.into_remainder());
+}
+
+/// `calculate_mandel_smooth` on `L::N` points, escaped lanes are frozen with a mask
+#[inline(always)]
+unsafe fn iterate<L: Lanes>(chunk: &mut [Mandel<L::S>]) {
+    let n_max = chunk[0].n_max;
+    debug_assert!(chunk.iter().all(|m| m.n_max == n_max));
+    let tolerance = chunk[0].period_tolerance();
+    debug_assert!(chunk.iter().all(|m| m.period_tolerance() == tolerance));
+    let zero = L::S::from_f64(0.);
+    let one = L::S::from_f64(1.);
+    let mut buf_re = [zero; 8];
+    let mut buf_im = [zero; 8];
+    // points in the main bulbs are done already, their lanes start frozen. with a trap the
+    // scalar code iterates them
+    let mut inside = [zero; 8];
+    let mut period = [0; 8];
+    for (i, m) in chunk.iter().enumerate() {
+        buf_re[i] = m.c.re;
+        buf_im[i] = m.c.im;
+        if let Some(p) = m.main_bulb_period().filter(|_| m.trap.is_none()) {
+            inside[i] = one;
+            period[i] = p;
+        }
+    }
+    let cr = L::load(&buf_re);
+    let ci = L::load(&buf_im);
+    let two = L::splat(L::S::from_f64(2.));
+    let bailout = L::splat(L::S::from_f64(M * M));
+    let tolerance = L::splat(tolerance);
+    let all = (1u32 << L::N) - 1;
+
+    let (mut zr, mut zi) = (cr, ci);
+    let mut dr = L::splat(one);
+    let mut di = L::splat(zero);
+    // escaped or periodic
+    let mut escaped = L::load(&inside).ge(L::splat(one));
+    let (mut saved_r, mut saved_i) = (cr, ci);
+    let mut saved_at = 1;
+    let mut n = [0; 8];
+    for i in 1..n_max {
+        if escaped.bits() == all {
+            break;
+        }
+        let sq = zr.mul(zr).add(zi.mul(zi));
+        let now = escaped.andnot(sq.ge(bailout));
+        let mut bits = now.bits();
+        if bits != 0 {
+            escaped = escaped.or(now);
+            while bits != 0 {
+                n[bits.trailing_zeros() as usize] = i;
+                bits &= bits - 1;
+            }
+        }
+        // derivative*z*2
+        let tr = dr.mul(zr).sub(di.mul(zi));
+        let ti = dr.mul(zi).add(di.mul(zr));
+        // + 1, the imaginary part too so -0 turns into 0 like in scalar
+        dr = escaped.select(tr.mul(two).add(L::splat(one)), dr);
+        di = escaped.select(ti.mul(two).add(L::splat(zero)), di);
+        // z.square() + c
+        let sr = zr.mul(zr).sub(zi.mul(zi)).add(cr);
+        let si = zr.mul(zi).mul(two).add(ci);
+        zr = escaped.select(sr, zr);
+        zi = escaped.select(si, zi);
+        // (z - saved).sq_abs() <= tolerance
+        let (er, ei) = (zr.sub(saved_r), zi.sub(saved_i));
+        let cycle = escaped.andnot(tolerance.ge(er.mul(er).add(ei.mul(ei))));
+        let mut bits = cycle.bits();
+        if bits != 0 {
+            escaped = escaped.or(cycle);
+            while bits != 0 {
+                period[bits.trailing_zeros() as usize] = i + 1 - saved_at;
+                bits &= bits - 1;
+            }
+        }
+        if (i + 1).is_power_of_two() {
+            saved_r = zr;
+            saved_i = zi;
+            saved_at = i + 1;
+        }
+    }
+
+    let (mut zr_out, mut zi_out, mut dr_out, mut di_out) = ([zero; 8], [zero; 8], [zero; 8], [zero; 8]);
+    zr.store(&mut zr_out);
+    zi.store(&mut zi_out);
+    dr.store(&mut dr_out);
+    di.store(&mut di_out);
+    for (i, m) in chunk.iter_mut().enumerate() {
+        m.period = period[i];
+        if inside[i] == one {
+            m.n = f64::INFINITY;
+            m.solve_interior();
+            continue;
+        }
+        m.n = if n[i] != 0 {n[i] as f64} else {f64::NAN};
+        m.finish_derivative(Cplx{re: zr_out[i], im: zi_out[i]}, Cplx{re: dr_out[i], im: di_out[i]});
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for __m256d {
+    type S = f64;
+    const N: usize = 4;
+    #[inline(always)]
+    unsafe fn splat(x: f64) -> Self {
+        _mm256_set1_pd(x)
+    }
+    #[inline(always)]
+    unsafe fn load(x: &[f64]) -> Self {
+        _mm256_loadu_pd(x[..Self::N].as_ptr())
+    }
+    #[inline(always)]
+    unsafe fn store(self, x: &mut [f64]) {
+        _mm256_storeu_pd(x[..Self::N].as_mut_ptr(), self)
+    }
+    #[inline(always)]
+    unsafe fn add(self, rhs: Self) -> Self {
+        _mm256_add_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn sub(self, rhs: Self) -> Self {
+        _mm256_sub_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn mul(self, rhs: Self) -> Self {
+        _mm256_mul_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn ge(self, rhs: Self) -> Self {
+        _mm256_cmp_pd::<_CMP_GE_OQ>(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn or(self, rhs: Self) -> Self {
+        _mm256_or_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn andnot(self, rhs: Self) -> Self {
+        _mm256_andnot_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn select(self, a: Self, b: Self) -> Self {
+        _mm256_blendv_pd(a, b, self)
+    }
+    #[inline(always)]
+    unsafe fn bits(self) -> u32 {
+        _mm256_movemask_pd(self) as u32
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for __m128d {
+    type S = f64;
+    const N: usize = 2;
+    #[inline(always)]
+    unsafe fn splat(x: f64) -> Self {
+        _mm_set1_pd(x)
+    }
+    #[inline(always)]
+    unsafe fn load(x: &[f64]) -> Self {
+        _mm_loadu_pd(x[..Self::N].as_ptr())
+    }
+    #[inline(always)]
+    unsafe fn store(self, x: &mut [f64]) {
+        _mm_storeu_pd(x[..Self::N].as_mut_ptr(), self)
+    }
+    #[inline(always)]
+    unsafe fn add(self, rhs: Self) -> Self {
+        _mm_add_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn sub(self, rhs: Self) -> Self {
+        _mm_sub_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn mul(self, rhs: Self) -> Self {
+        _mm_mul_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn ge(self, rhs: Self) -> Self {
+        _mm_cmpge_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn or(self, rhs: Self) -> Self {
+        _mm_or_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn andnot(self, rhs: Self) -> Self {
+        _mm_andnot_pd(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn select(self, a: Self, b: Self) -> Self {
+        // no blendv before sse4.1
+        _mm_or_pd(_mm_and_pd(self, b), _mm_andnot_pd(self, a))
+    }
+    #[inline(always)]
+    unsafe fn bits(self) -> u32 {
+        _mm_movemask_pd(self) as u32
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for __m256 {
+    type S = f32;
+    const N: usize = 8;
+    #[inline(always)]
+    unsafe fn splat(x: f32) -> Self {
+        _mm256_set1_ps(x)
+    }
+    #[inline(always)]
+    unsafe fn load(x: &[f32]) -> Self {
+        _mm256_loadu_ps(x[..Self::N].as_ptr())
+    }
+    #[inline(always)]
+    unsafe fn store(self, x: &mut [f32]) {
+        _mm256_storeu_ps(x[..Self::N].as_mut_ptr(), self)
+    }
+    #[inline(always)]
+    unsafe fn add(self, rhs: Self) -> Self {
+        _mm256_add_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn sub(self, rhs: Self) -> Self {
+        _mm256_sub_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn mul(self, rhs: Self) -> Self {
+        _mm256_mul_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn ge(self, rhs: Self) -> Self {
+        _mm256_cmp_ps::<_CMP_GE_OQ>(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn or(self, rhs: Self) -> Self {
+        _mm256_or_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn andnot(self, rhs: Self) -> Self {
+        _mm256_andnot_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn select(self, a: Self, b: Self) -> Self {
+        _mm256_blendv_ps(a, b, self)
+    }
+    #[inline(always)]
+    unsafe fn bits(self) -> u32 {
+        _mm256_movemask_ps(self) as u32
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for __m128 {
+    type S = f32;
+    const N: usize = 4;
+    #[inline(always)]
+    unsafe fn splat(x: f32) -> Self {
+        _mm_set1_ps(x)
+    }
+    #[inline(always)]
+    unsafe fn load(x: &[f32]) -> Self {
+        _mm_loadu_ps(x[..Self::N].as_ptr())
+    }
+    #[inline(always)]
+    unsafe fn store(self, x: &mut [f32]) {
+        _mm_storeu_ps(x[..Self::N].as_mut_ptr(), self)
+    }
+    #[inline(always)]
+    unsafe fn add(self, rhs: Self) -> Self {
+        _mm_add_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn sub(self, rhs: Self) -> Self {
+        _mm_sub_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn mul(self, rhs: Self) -> Self {
+        _mm_mul_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn ge(self, rhs: Self) -> Self {
+        _mm_cmpge_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn or(self, rhs: Self) -> Self {
+        _mm_or_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn andnot(self, rhs: Self) -> Self {
+        _mm_andnot_ps(self, rhs)
+    }
+    #[inline(always)]
+    unsafe fn select(self, a: Self, b: Self) -> Self {
+        _mm_or_ps(_mm_and_ps(self, b), _mm_andnot_ps(self, a))
+    }
+    #[inline(always)]
+    unsafe fn bits(self) -> u32 {
+        _mm_movemask_ps(self) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// every kernel the cpu has, the scalar one included
+    fn kernels() -> Vec<Kernel> {
+        let mut kernels = vec![Kernel::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sse2") {
+                kernels.push(Kernel::Sse2);
+            }
+            if is_x86_feature_detected!("avx2") {
+                kernels.push(Kernel::Avx2);
+            }
+        }
+        kernels
+    }
+
+    /// the bits of every result, NaN included
+    fn results(m: &Mandel) -> Vec<u64> {
+        let z = m.get_z();
+        let derivative = m.get_derivative();
+        vec![
+            m.n.to_bits(), z.re.to_bits(), z.im.to_bits(),
+            derivative.re.to_f64().to_bits(), derivative.im.to_f64().to_bits(),
+            m.normal.re.to_bits(), m.normal.im.to_bits(),
+            m.distance.to_f64().to_bits(), m.period as u64,
+            m.multiplier.re.to_bits(), m.multiplier.im.to_bits(), m.interior_distance.to_bits(),
+        ]
+    }
+
+    /// a grid over the whole set, an odd number of points so the last batch isn't full
+    fn check<T: SimdFloat>() {
+        let points: Vec<_> = (0..61).flat_map(|y| (0..61).map(move |x| Cplx{
+            re: T::from_f64(-2.2 + 3.*x as f64/60.),
+            im: T::from_f64(-1.3 + 2.6*y as f64/60.),
+        })).collect();
+        let mut expected: Vec<_> = points.iter().map(|&c| Mandel::new(c, 1000, 3./60., None, false)).collect();
+        scalar(&mut expected);
+        for kernel in kernels() {
+            let mut mandels: Vec<_> = points.iter().map(|&c| Mandel::new(c, 1000, 3./60., None, false)).collect();
+            T::calculate_with(kernel, &mut mandels);
+            for (i, (m, e)) in mandels.iter().zip(&expected).enumerate() {
+                assert!(results(&m.to_f64()) == results(&e.to_f64()), "{kernel} differs from the scalar code at point {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn kernels_match_scalar_f64() {
+        check::<f64>();
+    }
+
+    #[test]
+    fn kernels_match_scalar_f32() {
+        check::<f32>();
+    }
+}
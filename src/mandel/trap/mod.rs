@@ -0,0 +1,75 @@
+use super::cplx::Cplx;
+use super::float::Float;
+use super::Mandel;
+
+/// what the orbit is measured against
+#[derive(Clone, Copy, PartialEq)]
+pub enum TrapShape {
+    Point,
+    Line,
+    /// two perpendicular lines
+    Cross,
+    Circle,
+    /// Pickover stalks: a cross that only catches the orbit closer than `size`
+    Stalk,
+}
+
+impl std::fmt::Display for TrapShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TrapShape::Point => write!(f, "point"),
+            TrapShape::Line => write!(f, "line"),
+            TrapShape::Cross => write!(f, "cross"),
+            TrapShape::Circle => write!(f, "circle"),
+            TrapShape::Stalk => write!(f, "stalk"),
+        }
+    }
+}
+
+/// orbit trap, the orbit records how close it came to it and when
+#[derive(Clone, Copy, PartialEq)]
+pub struct Trap {
+    pub shape: TrapShape,
+    /// the point, the center of the circle and of the cross, a point of the line
+    pub center: Cplx<f64>,
+    /// radius of the circle, width of the stalks, distances are given in it
+    pub size: f64,
+    /// direction of the line and of the first arm of the cross, radians
+    pub angle: f64,
+}
+
+impl Trap {
+    /// distance from `z` to the trap in trap sizes, infinite when a stalk doesn't catch it
+    #[inline]
+    pub fn distance(&self, z: Cplx<f64>) -> f64 {
+        let d = z - self.center;
+        // coordinates along the line and across it
+        let (sin, cos) = self.angle.sin_cos();
+        let along = d.re*cos + d.im*sin;
+        let across = d.im*cos - d.re*sin;
+        let distance = match self.shape {
+            TrapShape::Point => d.abs(),
+            TrapShape::Line => across.abs(),
+            TrapShape::Cross | TrapShape::Stalk => across.abs().min(along.abs()),
+            TrapShape::Circle => (d.abs() - self.size).abs(),
+        } / self.size;
+        if self.shape == TrapShape::Stalk && distance >= 1. {
+            return f64::INFINITY;
+        }
+        distance
+    }
+}
+
+impl<T: Float> Mandel<T> {
+    /// records `z_i` if it's the closest to the trap so far
+    #[inline]
+    pub(super) fn check_trap<D: Float>(&mut self, z: &Cplx<D>, i: usize) {
+        if let Some(trap) = &self.trap {
+            let distance = trap.distance(z.to_f64());
+            if distance < self.trap_distance {
+                self.trap_distance = distance;
+                self.trap_iteration = i;
+            }
+        }
+    }
+}
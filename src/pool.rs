@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce(&Worker) + Send>;
+
+/// fixed set of threads running jobs, jobs can push more jobs
+///
+/// every worker has its own deque: it pushes and pops its own jobs at the back, so subdivisions
+/// are finished depth first and stay small, and steals from the front of the others when it runs
+/// out, which takes the oldest and biggest jobs.
+/// the threads are joined when the pool is dropped, jobs still queued then are dropped
+pub struct Pool {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+    /// deque for jobs spawned from outside the pool, round robin
+    next: AtomicUsize,
+}
+
+struct Shared {
+    queues: Vec<Mutex<VecDeque<Job>>>,
+    /// jobs in the queues, workers only sleep when it's 0
+    pending: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+}
+
+/// handle given to a running job to spawn more jobs on its own worker
+pub struct Worker<'a> {
+    shared: &'a Shared,
+    index: usize,
+}
+
+impl Pool {
+    /// `threads` workers, 0 for one per core
+    pub fn new(threads: usize) -> Self {
+        let threads = if threads == 0 {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            threads
+        };
+        let shared = Arc::new(Shared {
+            queues: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let threads = (0..threads).map(|index| {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("worker {index}"))
+                .spawn(move || shared.run(index))
+                .expect("failed to spawn worker thread")
+        }).collect();
+        Pool { shared, threads, next: AtomicUsize::new(0) }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    pub fn spawn(&self, job: impl FnOnce(&Worker) + Send + 'static) {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.threads.len();
+        self.shared.push(index, Box::new(job));
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        {
+            let _sleep = self.shared.sleep.lock().unwrap();
+            self.shared.wake.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Worker<'_> {
+    /// queues `job` on this worker, idle workers will steal it
+    pub fn spawn(&self, job: impl FnOnce(&Worker) + Send + 'static) {
+        self.shared.push(self.index, Box::new(job));
+    }
+}
+
+impl Shared {
+    fn push(&self, index: usize, job: Job) {
+        // counted first so `pop` never takes it below 0
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.queues[index].lock().unwrap().push_back(job);
+        // taking the lock orders this with a worker checking `pending` before it sleeps
+        let _sleep = self.sleep.lock().unwrap();
+        self.wake.notify_one();
+    }
+
+    /// own jobs newest first, then the oldest job of another worker
+    fn pop(&self, index: usize) -> Option<Job> {
+        let n = self.queues.len();
+        // one lock at a time, the guard of a temporary would live for the whole statement
+        let own = self.queues[index].lock().unwrap().pop_back();
+        let job = own.or_else(|| (1..n).find_map(|i| self.queues[(index + i) % n].lock().unwrap().pop_front()));
+        if job.is_some() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        job
+    }
+
+    fn run(&self, index: usize) {
+        let worker = Worker { shared: self, index };
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                return;
+            }
+            if let Some(job) = self.pop(index) {
+                job(&worker);
+                continue;
+            }
+            let sleep = self.sleep.lock().unwrap();
+            if self.pending.load(Ordering::SeqCst) == 0 && !self.shutdown.load(Ordering::SeqCst) {
+                drop(self.wake.wait(sleep).unwrap());
+            }
+        }
+    }
+}
+
+/// hands out the tokens of successive renders, starting one cancels all the older ones
+#[derive(Default)]
+pub struct Generations {
+    current: Arc<AtomicU64>,
+}
+
+/// shared by the jobs of one render, cancelled as soon as a newer generation starts
+#[derive(Clone)]
+pub struct CancelToken {
+    generation: u64,
+    current: Arc<AtomicU64>,
+}
+
+impl Generations {
+    pub fn next(&self) -> CancelToken {
+        let generation = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        CancelToken { generation, current: self.current.clone() }
+    }
+}
+
+impl CancelToken {
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.current.load(Ordering::Relaxed) != self.generation
+    }
+}